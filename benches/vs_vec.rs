@@ -0,0 +1,80 @@
+//! Compares buffering an iterator of unknown length on a second stack
+//! with collecting it into a Vec. Run with `cargo bench --bench vs_vec`.
+
+use second_stack::Stack;
+use std::{hint::black_box, time::Instant};
+
+fn time(name: &str, iterations: u32, mut f: impl FnMut()) {
+    // Warm up
+    f();
+    let start = Instant::now();
+    for _ in 0..iterations {
+        f();
+    }
+    let elapsed = start.elapsed() / iterations;
+    println!("{name:<32} {elapsed:>12?}");
+}
+
+fn main() {
+    for len in [100u64, 10_000, 1_000_000] {
+        let iterations = (100_000_000 / len).min(100_000) as u32;
+        // The filter hides the length, so that each has to grow as it goes
+        let items = || (0..len).filter(|i| black_box(*i) != u64::MAX);
+
+        time(&format!("vec/{len}"), iterations, || {
+            black_box(items().collect::<Vec<_>>());
+        });
+
+        // A new stack has to grow to fit the items. With nothing else
+        // in the allocation, it grows with realloc.
+        time(
+            &format!("buffer/new-stack/realloc/{len}"),
+            iterations,
+            || {
+                Stack::new().buffer(items(), |items| {
+                    black_box(items);
+                });
+            },
+        );
+
+        // A slice held below the items stops realloc from being used,
+        // so each time it grows the items are copied to a new allocation
+        time(&format!("buffer/new-stack/copy/{len}"), iterations, || {
+            let stack = Stack::new();
+            stack.uninit_slice(1, |_: &mut [std::mem::MaybeUninit<u8>]| {
+                stack.buffer(items(), |items| {
+                    black_box(items);
+                });
+            });
+        });
+
+        // A stack that has been used before already has room
+        let stack = Stack::new();
+        time(&format!("buffer/warm-stack/{len}"), iterations, || {
+            stack.buffer(items(), |items| {
+                black_box(items);
+            });
+        });
+
+        time(
+            &format!("buffer_to_vec/warm-stack/{len}"),
+            iterations,
+            || {
+                black_box(stack.buffer_to_vec(items()));
+            },
+        );
+
+        // With a known length, buffer_copy skips checking for room per item
+        let data = items().collect::<Vec<_>>();
+        time(&format!("buffer/slice/{len}"), iterations, || {
+            stack.buffer(data.iter().copied(), |items| {
+                black_box(items);
+            });
+        });
+        time(&format!("buffer_copy/slice/{len}"), iterations, || {
+            stack.buffer_copy(data.iter().copied(), |items| {
+                black_box(items);
+            });
+        });
+    }
+}
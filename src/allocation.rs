@@ -1,16 +1,142 @@
-use std::{
+use core::{
     cell::UnsafeCell,
+    fmt,
     mem::{self, align_of, replace, size_of},
     ptr,
 };
 
-use crate::DropStack;
+use crate::{growth::Policy, DropStack};
+use alloc::{
+    alloc::{handle_alloc_error, Layout},
+    vec::Vec,
+};
+
+/// The number of bytes in a slice of len T.
+/// Panics if this overflows, rather than under-allocating. A slice
+/// may be no larger than isize::MAX bytes, so neither may this.
+pub(crate) fn slice_bytes<T>(len: usize) -> usize {
+    checked_bytes(
+        size_of::<T>()
+            .checked_mul(len)
+            .filter(|&bytes| bytes <= MAX_CAPACITY),
+    )
+}
+
+pub(crate) fn checked_bytes(bytes: Option<usize>) -> usize {
+    match bytes {
+        Some(bytes) => bytes,
+        None => panic!("second-stack: allocation size overflow"),
+    }
+}
+
+// The largest capacity that the allocator will accept
+const MAX_CAPACITY: usize = isize::MAX as usize;
+
+#[cfg(all(unix, any(feature = "madvise", feature = "prefault")))]
+fn page_size() -> Option<usize> {
+    match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+        size if size > 0 => Some(size as usize),
+        _ => None,
+    }
+}
+
+/// How a stack grows, as set by [`StackBuilder`](crate::StackBuilder)
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Config {
+    pub min_capacity: usize,
+    // Greater than 1
+    pub growth_factor: f64,
+    // Slices of more bytes than this go to the heap instead
+    pub heap_threshold: usize,
+    // The number of quiet returns to empty before shrinking
+    #[cfg(feature = "adaptive")]
+    pub decay_window: usize,
+    // Replaces min_capacity and growth_factor when set
+    pub policy: Option<Policy>,
+}
+
+impl Config {
+    pub const DEFAULT: Config = Config {
+        min_capacity: 64,
+        growth_factor: 2.0,
+        heap_threshold: 16 << 20,
+        #[cfg(feature = "adaptive")]
+        decay_window: 64,
+        policy: None,
+    };
+
+    /// The capacity of the allocation to replace one of capacity current
+    /// when at least required bytes are needed.
+    pub fn next_capacity(&self, current: usize, required: usize) -> usize {
+        if let Some(Policy(policy)) = self.policy {
+            return policy.next_capacity(current, required).max(required);
+        }
+        // Require at least min_capacity bytes for the smallest allocation,
+        // and require we grow by at least the growth factor from the
+        // previous allocated stack
+        let mut new_capacity = self.min_capacity.max(self.grow(current));
+        // Keep growing until the desired slice fits. With the
+        // default settings, this is always a power of 2.
+        while new_capacity < required {
+            new_capacity = self.grow(new_capacity);
+        }
+        // When growing overshoots what can be allocated,
+        // fall back to exactly what was asked for.
+        if new_capacity > MAX_CAPACITY {
+            new_capacity = required;
+        }
+        new_capacity
+    }
+
+    fn grow(&self, capacity: usize) -> usize {
+        // The cast saturates rather than overflowing. The max ensures
+        // progress for a small capacity and a small factor.
+        let grown = (capacity as f64 * self.growth_factor) as usize;
+        grown.max(capacity.saturating_add(1))
+    }
+}
+
+/// The error returned by the fallible methods of [`Stack`](crate::Stack)
+/// when memory for the stack could not be allocated.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct AllocError;
+
+impl fmt::Display for AllocError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("memory allocation failed")
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for AllocError {}
 
 #[derive(Clone)]
 pub(crate) struct Allocation {
     pub base: *mut u8,
     pub len: usize,
     pub capacity: usize,
+    // The largest len reached by this or any earlier allocation of the stack
+    #[cfg(feature = "stats")]
+    pub peak: usize,
+    // The number of live DropStacks for the stack, in any of its allocations
+    #[cfg(feature = "stats")]
+    pub live: usize,
+    #[cfg(feature = "adaptive")]
+    pub decay: Decay,
+    // Carried over to each new allocation of the stack
+    pub config: Config,
+}
+
+/// Tracks how much of an allocation has been used recently, so that
+/// it can be shrunk once the stack stays well below its capacity.
+#[cfg(feature = "adaptive")]
+#[derive(Copy, Clone, Debug, Default)]
+pub(crate) struct Decay {
+    // The largest len since the stack was last empty
+    high: usize,
+    // How many times in a row the stack has been empty
+    // after using at most a quarter of its capacity
+    quiet: usize,
 }
 
 impl Allocation {
@@ -19,53 +145,299 @@ impl Allocation {
         parent: &'a UnsafeCell<Allocation>,
         len: usize,
     ) -> (DropStack<'a>, (*mut T, usize)) {
-        unsafe {
-            // Requires at a minimum size * len, but at a maximum must also pay
-            // an alignment cost.
-            let required_bytes_pessimistic = (align_of::<T>() - 1) + (size_of::<T>() * len);
-            self.ensure_capacity(required_bytes_pessimistic);
-
-            let restore = self.clone();
-            let base = self.base.offset(self.len as isize);
-            let align = base.align_offset(align_of::<T>());
-            let ptr = base.offset(align as isize);
-            self.len += align + (size_of::<T>() * len);
-
-            (
-                DropStack {
-                    restore,
-                    location: parent,
-                },
-                (ptr as *mut T, len),
-            )
+        let (restore, slice, _padding) = self.get_slice_padded(parent, len);
+        (restore, slice)
+    }
+
+    /// Like get_slice, but also returns the number of bytes that were
+    /// skipped to align the slice.
+    pub fn get_slice_padded<'a, T>(
+        &mut self,
+        parent: &'a UnsafeCell<Allocation>,
+        len: usize,
+    ) -> (DropStack<'a>, (*mut T, usize), usize) {
+        self.get_slice_aligned(parent, len, align_of::<T>())
+    }
+
+    /// Like get_slice_padded, but aligns the slice to align, which
+    /// must be a power of two no less than the alignment of T.
+    pub fn get_slice_aligned<'a, T>(
+        &mut self,
+        parent: &'a UnsafeCell<Allocation>,
+        len: usize,
+        align: usize,
+    ) -> (DropStack<'a>, (*mut T, usize), usize) {
+        if let Some(bytes) = self.required_capacity::<T>(len, align) {
+            self.ensure_capacity(bytes);
+        }
+        unsafe { self.bump_with_restore(parent, len, align) }
+    }
+
+    /// Like get_slice_aligned, but returns an error rather than aborting
+    /// if a new allocation is needed and cannot be made. The stack is
+    /// unchanged when this fails.
+    pub fn try_get_slice_aligned<'a, T>(
+        &mut self,
+        parent: &'a UnsafeCell<Allocation>,
+        len: usize,
+        align: usize,
+    ) -> Result<(DropStack<'a>, (*mut T, usize), usize), AllocError> {
+        if let Some(bytes) = self.required_capacity::<T>(len, align) {
+            self.try_ensure_capacity(bytes)?;
+        }
+        Ok(unsafe { self.bump_with_restore(parent, len, align) })
+    }
+
+    /// The capacity needed to fit a slice of len T aligned to align,
+    /// or None if it already fits.
+    fn required_capacity<T>(&self, len: usize, align: usize) -> Option<usize> {
+        debug_assert!(align.is_power_of_two() && align >= align_of::<T>());
+        // When the slice fits in the current allocation, only the actual
+        // alignment cost for the top of the stack is paid. That way, a
+        // slice following one of the same type is placed directly after it.
+        let slice_bytes = slice_bytes::<T>(len);
+        let required_bytes = checked_bytes(self.padding_to(align).checked_add(slice_bytes));
+        if self.remaining_bytes() >= required_bytes {
+            return None;
+        }
+        // The alignment of a new allocation is not yet known, so
+        // requires at a minimum size * len, but at a maximum must
+        // also pay an alignment cost.
+        Some(checked_bytes((align - 1).checked_add(slice_bytes)))
+    }
+
+    /// Makes room for a slice of len_a A directly followed by a slice of
+    /// len_b B, so that taking the second slice does not move on to a new
+    /// allocation. A slice which takes no memory is left out.
+    pub fn reserve_pair<A, B>(&mut self, len_a: usize, len_b: usize) {
+        fn region<T>(len: usize) -> (usize, usize) {
+            if size_of::<T>() == 0 || len == 0 {
+                (0, 1)
+            } else {
+                (slice_bytes::<T>(len), align_of::<T>())
+            }
+        }
+        let (bytes_a, align_a) = region::<A>(len_a);
+        let (bytes_b, align_b) = region::<B>(len_b);
+
+        // The exact cost of placing both at the top of this allocation
+        let top = self.base.wrapping_add(self.len);
+        let padding_a = self.padding_to(align_a);
+        let end_a = checked_bytes(padding_a.checked_add(bytes_a));
+        let padding_b = top.wrapping_add(end_a).align_offset(align_b);
+        let required_bytes = checked_bytes(
+            end_a
+                .checked_add(padding_b)
+                .and_then(|bytes| bytes.checked_add(bytes_b)),
+        );
+        if self.remaining_bytes() >= required_bytes {
+            return;
+        }
+
+        // The worst case for a new allocation of unknown alignment
+        let bytes = (align_a - 1)
+            .checked_add(bytes_a)
+            .and_then(|bytes| bytes.checked_add(align_b - 1))
+            .and_then(|bytes| bytes.checked_add(bytes_b));
+        self.ensure_capacity(checked_bytes(bytes));
+    }
+
+    /// Takes the slice from the top of the stack, along with the
+    /// restore that gives it back.
+    ///
+    /// Safety: There must be capacity for the slice, including padding.
+    unsafe fn bump_with_restore<'a, T>(
+        &mut self,
+        parent: &'a UnsafeCell<Allocation>,
+        len: usize,
+        align: usize,
+    ) -> (DropStack<'a>, (*mut T, usize), usize) {
+        let restore = self.clone();
+        let (ptr, padding) = self.bump_aligned(len, align);
+
+        (DropStack::new(restore, parent), (ptr, len), padding)
+    }
+
+    /// The number of bytes needed to align the top of the stack for T
+    pub fn padding<T>(&self) -> usize {
+        self.padding_to(align_of::<T>())
+    }
+
+    /// The number of bytes needed to align the top of the stack to align
+    pub fn padding_to(&self, align: usize) -> usize {
+        self.base.wrapping_add(self.len).align_offset(align)
+    }
+
+    /// Moves the top of the stack past an aligned slice of len T, returning
+    /// the start of that slice and the padding that was skipped to get there.
+    ///
+    /// Safety: The caller must have ensured that there is capacity for the
+    /// slice, including padding.
+    pub unsafe fn bump<T>(&mut self, len: usize) -> (*mut T, usize) {
+        self.bump_aligned(len, align_of::<T>())
+    }
+
+    /// Like bump, but aligns the slice to align.
+    ///
+    /// Safety: As bump, and align must be no less than the alignment of T.
+    pub unsafe fn bump_aligned<T>(&mut self, len: usize, align: usize) -> (*mut T, usize) {
+        let padding = self.padding_to(align);
+        let start = self.len + padding;
+        // Neither of these overflow, since the caller has checked
+        // that the end is within capacity, which is at most isize::MAX.
+        let end = start + size_of::<T>() * len;
+        debug_assert!(end <= self.capacity, "second-stack: bump past capacity");
+        let ptr = self.base.add(start);
+        self.len = end;
+        self.update_peak();
+        (ptr as *mut T, padding)
+    }
+
+    /// Gives back the memory above len, scrubbing it, while a restore for
+    /// this allocation is still held. Returns false without doing anything
+    /// if that would leave nothing in use: the stack must not look idle
+    /// while the restore is held, or the allocation could be freed out
+    /// from under it, and then freed again by the restore.
+    pub fn give_back_to(&mut self, len: usize) -> bool {
+        debug_assert!(len <= self.len, "second-stack: give back above the top");
+        if len == 0 {
+            return false;
+        }
+        unsafe { crate::scrub(self.base.add(len), self.len - len) };
+        self.len = len;
+        true
+    }
+
+    /// Records the current len in the high-water mark, if enabled.
+    #[inline]
+    pub fn update_peak(&mut self) {
+        #[cfg(feature = "stats")]
+        {
+            self.peak = self.peak.max(self.len);
+        }
+        #[cfg(feature = "adaptive")]
+        {
+            self.decay.high = self.decay.high.max(self.len);
+        }
+    }
+
+    /// Grows the allocation so that at least bytes are available,
+    /// unless it is in use.
+    pub fn reserve(&mut self, bytes: usize) {
+        if self.len == 0 {
+            self.ensure_capacity(bytes);
         }
     }
+
+    /// Replaces the allocation with a smaller one with room for at least
+    /// bytes, rounded up as when growing, if nothing is in use.
+    pub fn shrink_to(&mut self, bytes: usize) {
+        if self.len != 0 || self.base.is_null() {
+            return;
+        }
+        let capacity = self.config.next_capacity(0, bytes);
+        if capacity < self.capacity {
+            self.replace_with(Allocation::new(capacity));
+        }
+    }
+
+    /// Takes the memory of other in place of this allocation if it is
+    /// larger, leaving other empty. Both must be idle.
+    pub fn adopt(&mut self, other: &mut Allocation) {
+        if self.len != 0 || other.len != 0 || other.capacity <= self.capacity {
+            return;
+        }
+        let allocation = Allocation::from_raw_parts(other.base, other.capacity);
+        other.base = ptr::null_mut();
+        other.capacity = 0;
+        self.replace_with(allocation);
+    }
+
     fn ensure_capacity(&mut self, capacity: usize) {
         if self.remaining_bytes() < capacity {
-            // Require at least 64 bytes for the smallest allocation,
-            // and require we at least double in size from the previous
-            // allocated stack
-            let mut new_capacity = 64.max(self.capacity * 2);
-            // Require that we are a power of 2 and can fit
-            // the desired slice.
-            while new_capacity < capacity {
-                new_capacity *= 2;
+            let new_capacity = self.config.next_capacity(self.capacity, capacity);
+            self.replace_with(Allocation::new(new_capacity));
+        }
+        debug_assert!(self.remaining_bytes() >= capacity);
+    }
+
+    fn try_ensure_capacity(&mut self, capacity: usize) -> Result<(), AllocError> {
+        if self.remaining_bytes() < capacity {
+            let new_capacity = self.config.next_capacity(self.capacity, capacity);
+            // Under memory pressure, doubling may fail where
+            // the size that is actually needed would not.
+            let allocation =
+                Allocation::try_new(new_capacity).or_else(|_| Allocation::try_new(capacity))?;
+            self.replace_with(allocation);
+        }
+        debug_assert!(self.remaining_bytes() >= capacity);
+        Ok(())
+    }
+
+    fn replace_with(&mut self, allocation: Allocation) {
+        let mut dealloc = replace(self, allocation);
+        #[cfg(feature = "instrument")]
+        crate::instrument::notify(dealloc.capacity, self.capacity);
+        #[cfg(feature = "tracing")]
+        crate::trace::capacity_changed(dealloc.capacity, self.capacity);
+        self.config = dealloc.config;
+        #[cfg(feature = "stats")]
+        {
+            self.peak = dealloc.peak;
+            self.live = dealloc.live;
+        }
+        // If the previous stack was not borrowed, we need to
+        // free it.
+        dealloc.try_dealloc();
+    }
+
+    /// Called each time the stack becomes empty. Halves the allocation
+    /// once the stack has used at most a quarter of it decay_window
+    /// times in a row, so that the capacity follows the recent peak.
+    #[cfg(feature = "adaptive")]
+    pub fn decay(&mut self) {
+        debug_assert_eq!(self.len, 0);
+        let decay = &mut self.decay;
+        if decay.high <= self.capacity / 4 {
+            decay.quiet += 1;
+        } else {
+            decay.quiet = 0;
+        }
+        decay.high = 0;
+        if decay.quiet < self.config.decay_window {
+            return;
+        }
+        decay.quiet = 0;
+
+        let capacity = (self.capacity / 2).max(self.config.min_capacity);
+        if capacity < self.capacity {
+            if cfg!(feature = "zeroize") {
+                // realloc may free the old memory without it being wiped.
+                // Nothing is in use, so there is nothing to copy, and the
+                // old allocation is scrubbed as it is freed.
+                self.replace_with(Allocation::new(capacity));
+            } else {
+                // Safety: Nothing is in use, so nothing points into the allocation
+                unsafe { self.realloc(capacity) }
             }
-            let mut dealloc = replace(self, Allocation::new(new_capacity));
-            // If the previous stack was not borrowed, we need to
-            // free it.
-            dealloc.try_dealloc();
         }
     }
 
     pub fn ref_eq(&self, other: &Self) -> bool {
         self.base == other.base
     }
-    pub fn null() -> Self {
+    pub const fn null() -> Self {
         Self {
             base: ptr::null_mut(),
             len: 0,
             capacity: 0,
+            #[cfg(feature = "stats")]
+            peak: 0,
+            #[cfg(feature = "stats")]
+            live: 0,
+            #[cfg(feature = "adaptive")]
+            decay: Decay { high: 0, quiet: 0 },
+            config: Config::DEFAULT,
         }
     }
 
@@ -74,31 +446,141 @@ impl Allocation {
     }
 
     pub fn new(size_in_bytes: usize) -> Self {
-        let mut v = Vec::<u8>::with_capacity(size_in_bytes);
-        let base = v.as_mut_ptr();
-        mem::forget(v);
+        #[cfg(feature = "pool")]
+        if let Some(base) = crate::pool::take(size_in_bytes) {
+            return Self::from_raw_parts(base, size_in_bytes);
+        }
+        let mut v = mem::ManuallyDrop::new(Vec::with_capacity(size_in_bytes));
+        Self::from_raw_parts(v.as_mut_ptr(), v.capacity())
+    }
 
-        // println!("Alloc {size_in_bytes} bytes at {base:?}");
+    pub fn try_new(size_in_bytes: usize) -> Result<Self, AllocError> {
+        #[cfg(feature = "pool")]
+        if let Some(base) = crate::pool::take(size_in_bytes) {
+            return Ok(Self::from_raw_parts(base, size_in_bytes));
+        }
+        let mut v = Vec::new();
+        v.try_reserve_exact(size_in_bytes).map_err(|_| AllocError)?;
+        let mut v = mem::ManuallyDrop::new(v);
+        Ok(Self::from_raw_parts(v.as_mut_ptr(), v.capacity()))
+    }
+
+    fn from_raw_parts(base: *mut u8, capacity: usize) -> Self {
+        // println!("Alloc {capacity} bytes at {base:?}");
 
         Self {
             base,
             len: 0,
-            capacity: size_in_bytes,
+            capacity,
+            #[cfg(feature = "stats")]
+            peak: 0,
+            #[cfg(feature = "stats")]
+            live: 0,
+            #[cfg(feature = "adaptive")]
+            decay: Decay { high: 0, quiet: 0 },
+            config: Config::DEFAULT,
         }
     }
 
+    /// Grows the allocation to capacity bytes using the allocator's realloc,
+    /// which may be able to extend it without moving. The contents are kept,
+    /// but base may change.
+    ///
+    /// Safety: Nothing may point into the allocation, other than
+    /// the caller who will update their pointers.
+    pub unsafe fn realloc(&mut self, capacity: usize) {
+        // Matches the layout of the Vec<u8> that made the allocation
+        let layout = Layout::array::<u8>(self.capacity).unwrap();
+        let base = alloc::alloc::realloc(self.base, layout, capacity);
+        if base.is_null() {
+            handle_alloc_error(Layout::array::<u8>(capacity).unwrap());
+        }
+        #[cfg(feature = "instrument")]
+        crate::instrument::notify(self.capacity, capacity);
+        #[cfg(feature = "tracing")]
+        crate::trace::capacity_changed(self.capacity, capacity);
+        self.base = base;
+        self.capacity = capacity;
+    }
+
     pub unsafe fn force_dealloc(&mut self) {
-        if self.base == ptr::null_mut() {
+        if self.base.is_null() {
             return;
         }
 
-        // println!("Dealloc {} bytes at {:?}", self.capacity, self.base,);
-        // Deallocates the memory
-        drop(Vec::from_raw_parts(self.base, 0, self.capacity));
+        #[cfg(feature = "instrument")]
+        crate::instrument::notify(self.capacity, 0);
+        #[cfg(feature = "tracing")]
+        crate::trace::capacity_changed(self.capacity, 0);
+
+        #[cfg(feature = "zeroize")]
+        crate::scrub(self.base, self.capacity);
+
+        #[cfg(feature = "pool")]
+        let pooled = crate::pool::give(self.base, self.capacity);
+        #[cfg(not(feature = "pool"))]
+        let pooled = false;
+
+        if !pooled {
+            // println!("Dealloc {} bytes at {:?}", self.capacity, self.base,);
+            // Deallocates the memory
+            drop(Vec::from_raw_parts(self.base, 0, self.capacity));
+        }
 
         self.base = ptr::null_mut();
     }
 
+    /// Writes a zero to one byte in each page of this allocation, so that
+    /// the OS maps all of the pages now. The allocation must be idle, since
+    /// its contents are not kept.
+    #[cfg(all(unix, feature = "prefault"))]
+    pub fn prefault(&mut self) {
+        if self.base.is_null() || self.len != 0 {
+            return;
+        }
+
+        let page = match page_size() {
+            Some(page) => page,
+            None => return,
+        };
+        let mut offset = 0;
+        while offset < self.capacity {
+            unsafe {
+                let byte = self.base.add(offset);
+                // Volatile so that the write is not optimized out. Nothing
+                // is read, since the memory may never have been written.
+                byte.write_volatile(0);
+            }
+            // The first byte of each following page
+            offset = (self.base as usize + offset + 1).next_multiple_of(page) - self.base as usize;
+        }
+    }
+
+    /// Advises the OS that the whole pages within this allocation are not
+    /// needed, so that they stop counting towards the resident set. The
+    /// allocation is kept, and the pages are faulted back in on next use.
+    #[cfg(all(unix, feature = "madvise"))]
+    pub fn release_pages(&mut self) {
+        if self.base.is_null() || self.len != 0 {
+            return;
+        }
+
+        let page = match page_size() {
+            Some(page) => page,
+            None => return,
+        };
+        // Only pages entirely within the allocation may be released,
+        // since the allocator may keep other data in the rest.
+        let start = (self.base as usize).next_multiple_of(page);
+        let end = (self.base as usize + self.capacity) / page * page;
+        if start < end {
+            unsafe {
+                // A failure leaves the pages resident, which is harmless.
+                libc::madvise(start as *mut libc::c_void, end - start, libc::MADV_DONTNEED);
+            }
+        }
+    }
+
     pub fn try_dealloc(&mut self) {
         // Don't dealloc if the slice is in-use.
         // We assume at this point that there are no slices with len
@@ -112,3 +594,44 @@ impl Allocation {
         unsafe { self.force_dealloc() }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn next_capacity_doubles() {
+        assert_eq!(Config::DEFAULT.next_capacity(0, 1), 64);
+        assert_eq!(Config::DEFAULT.next_capacity(64, 65), 128);
+        assert_eq!(Config::DEFAULT.next_capacity(100, 1000), 1600);
+        assert_eq!(Config::DEFAULT.next_capacity(1 << 20, 1), 1 << 21);
+    }
+
+    #[test]
+    fn next_capacity_does_not_overflow() {
+        let required = usize::MAX / 2 - 100;
+        assert_eq!(Config::DEFAULT.next_capacity(0, required), required);
+        assert_eq!(Config::DEFAULT.next_capacity(1 << 40, required), required);
+        assert_eq!(Config::DEFAULT.next_capacity(usize::MAX / 2 + 1, 64), 64);
+        assert_eq!(Config::DEFAULT.next_capacity(0, usize::MAX), usize::MAX);
+    }
+
+    #[test]
+    fn next_capacity_growth_factor() {
+        let config = Config {
+            growth_factor: 1.5,
+            ..Config::DEFAULT
+        };
+        assert_eq!(config.next_capacity(0, 1), 64);
+        assert_eq!(config.next_capacity(0, 100), 144);
+        assert_eq!(config.next_capacity(1000, 1001), 1500);
+        assert_eq!(config.next_capacity(usize::MAX / 2, 64), 64);
+
+        let config = Config {
+            min_capacity: 1,
+            growth_factor: 1.01,
+            ..Config::DEFAULT
+        };
+        assert_eq!(config.next_capacity(0, 3), 3);
+    }
+}
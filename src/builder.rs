@@ -0,0 +1,89 @@
+use core::cell::UnsafeCell;
+
+use crate::{
+    allocation::{Allocation, Config},
+    growth::{GrowthPolicy, Policy},
+    Stack,
+};
+
+/// Configures how a [`Stack`] grows, created by [`Stack::builder`].
+#[derive(Clone, Debug)]
+pub struct StackBuilder {
+    config: Config,
+}
+
+impl Default for StackBuilder {
+    fn default() -> Self {
+        Self {
+            config: Config::DEFAULT,
+        }
+    }
+}
+
+impl StackBuilder {
+    /// The smallest allocation that the stack will make, in bytes.
+    /// The default is 64.
+    pub fn min_capacity(mut self, bytes: usize) -> Self {
+        // Growth is by multiplying the capacity, so it can't start at 0
+        self.config.min_capacity = bytes.max(1);
+        self
+    }
+
+    /// How much larger each new allocation is than the last, when the stack
+    /// needs to grow. The default is 2. A smaller factor lowers the peak
+    /// memory use, at the cost of growing more often. Panics if the factor
+    /// is not greater than 1.
+    ///
+    /// This only affects the stack's own allocations. When a method like
+    /// [`Stack::buffer`] runs out of room for items within an allocation,
+    /// it still doubles the room it uses, which is free while the
+    /// allocation has space left.
+    pub fn growth_factor(mut self, factor: f64) -> Self {
+        assert!(
+            factor > 1.0,
+            "second-stack: growth_factor must be greater than 1"
+        );
+        self.config.growth_factor = factor;
+        self
+    }
+
+    /// Chooses the size of each new allocation with `policy`, such as
+    /// [`Exact`](crate::Exact) or [`Factor`](crate::Factor), in place of
+    /// [`min_capacity`](StackBuilder::min_capacity) and
+    /// [`growth_factor`](StackBuilder::growth_factor).
+    pub fn growth_policy(mut self, policy: &'static dyn GrowthPolicy) -> Self {
+        self.config.policy = Some(Policy(policy));
+        self
+    }
+
+    /// The size in bytes above which [`Stack::uninit_slice`] and
+    /// [`Stack::try_uninit_slice`] make a one-off heap allocation for the
+    /// slice, rather than growing the stack to hold it and keeping that
+    /// memory afterwards. The default is 16MiB, which is also used by the
+    /// threadlocal stack. Use `usize::MAX` to always use the stack.
+    pub fn heap_threshold(mut self, bytes: usize) -> Self {
+        self.config.heap_threshold = bytes;
+        self
+    }
+
+    /// How many times in a row the stack must become empty after using at
+    /// most a quarter of its capacity before the capacity is halved. The
+    /// default is 64. A stack which is occasionally used heavily keeps its
+    /// capacity, while one which was used heavily once gives the memory back
+    /// over time. Panics if the window is 0.
+    #[cfg(feature = "adaptive")]
+    pub fn decay_window(mut self, returns: usize) -> Self {
+        assert!(returns != 0, "second-stack: decay_window must be non-zero");
+        self.config.decay_window = returns;
+        self
+    }
+
+    /// Creates the stack. No memory is allocated until the first
+    /// slice is requested.
+    pub fn build(self) -> Stack {
+        Stack(UnsafeCell::new(Allocation {
+            config: self.config,
+            ..Allocation::null()
+        }))
+    }
+}
@@ -0,0 +1,93 @@
+//! C ABI entry points, so that a C host can take scratch space from the
+//! same threadlocal stack as the Rust code it calls into.
+//!
+//! ```c
+//! uint8_t *second_stack_reserve(size_t len, size_t align);
+//! void second_stack_release(uint8_t *ptr);
+//! ```
+
+use core::{
+    cell::RefCell,
+    mem::{self, align_of},
+    ptr,
+};
+
+use crate::{threadlocal::with_stack, Allocation, DropStack};
+
+thread_local!(
+    // The reservations that have not been released, in the order they
+    // were made, with the restore for each. Reservations which do not
+    // use the stack have no restore.
+    static RESERVED: RefCell<Vec<(*mut u8, Option<Allocation>)>> = const { RefCell::new(Vec::new()) }
+);
+
+/// Takes `len` bytes aligned to `align` from the threadlocal stack, and
+/// returns a pointer to them. They are uninit, and stay in use until
+/// given to [`second_stack_release`]. Returns null if `align` is not a
+/// power of two. The process is aborted if the memory cannot be allocated.
+#[no_mangle]
+pub extern "C" fn second_stack_reserve(len: usize, align: usize) -> *mut u8 {
+    if !align.is_power_of_two() {
+        return ptr::null_mut();
+    }
+    let align = align.max(align_of::<u8>());
+
+    let reservation = if len == 0 {
+        // See also: 26936c11-5b7c-472e-8f63-7922e63a5425
+        (ptr::without_provenance_mut(align), None)
+    } else {
+        with_stack(|stack| {
+            let location = &stack.0;
+            let (restore, (ptr, _len), _padding) = unsafe {
+                let stack = &mut *location.get();
+                stack.get_slice_aligned::<u8>(location, len, align)
+            };
+            // Given back on release instead
+            let allocation = restore.restore.clone();
+            mem::forget(restore);
+            (ptr, Some(allocation))
+        })
+    };
+    RESERVED.with(|reserved| reserved.borrow_mut().push(reservation.clone()));
+    reservation.0
+}
+
+/// Gives back the bytes at `ptr`, which must be from the most recent
+/// [`second_stack_reserve`] on this thread that has not been released.
+/// Releasing out of order is caught by a debug assertion. Otherwise, any
+/// reservations made since are released as well.
+///
+/// # Safety
+/// The bytes must not be used after this. Nothing taken from the
+/// threadlocal stack by Rust since the reservation may still be in use,
+/// such as when this is called from within a callback.
+#[no_mangle]
+pub unsafe extern "C" fn second_stack_release(ptr: *mut u8) {
+    RESERVED.with(|reserved| {
+        let mut reserved = reserved.borrow_mut();
+        let Some(index) = reserved.iter().rposition(|(reserved, _)| *reserved == ptr) else {
+            debug_assert!(
+                false,
+                "second-stack: released a pointer that was not reserved"
+            );
+            return;
+        };
+        debug_assert_eq!(
+            index,
+            reserved.len() - 1,
+            "second-stack: reservations released out of order"
+        );
+        // Latest first, as the restores would run for nested slices
+        with_stack(|stack| {
+            while reserved.len() > index {
+                let (_ptr, restore) = reserved.pop().unwrap();
+                if let Some(restore) = restore {
+                    drop(DropStack {
+                        restore,
+                        location: &stack.0,
+                    });
+                }
+            }
+        });
+    });
+}
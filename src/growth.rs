@@ -0,0 +1,74 @@
+//! Strategies for choosing the size of a stack's next allocation, for use
+//! with [`StackBuilder::growth_policy`](crate::StackBuilder::growth_policy).
+
+use core::fmt;
+
+use crate::allocation::Config;
+
+/// Chooses the capacity of a stack's next allocation when it needs to grow.
+pub trait GrowthPolicy {
+    /// The capacity in bytes to replace an allocation of `current` bytes
+    /// with, when at least `required` bytes are needed. A result smaller
+    /// than `required` is raised to `required`.
+    fn next_capacity(&self, current: usize, required: usize) -> usize;
+}
+
+/// Doubles the capacity, starting from 64 bytes, until the slice fits.
+/// This is the default.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Doubling;
+
+impl GrowthPolicy for Doubling {
+    fn next_capacity(&self, current: usize, required: usize) -> usize {
+        Config::DEFAULT.next_capacity(current, required)
+    }
+}
+
+/// Allocates only what is needed. This keeps memory use to a minimum, but
+/// a stack which is used more and more heavily allocates each time.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Exact;
+
+impl GrowthPolicy for Exact {
+    fn next_capacity(&self, _current: usize, required: usize) -> usize {
+        required
+    }
+}
+
+/// Grows the capacity by a factor, starting from 64 bytes, until the
+/// slice fits.
+#[derive(Copy, Clone, Debug)]
+pub struct Factor(f64);
+
+impl Factor {
+    /// Panics if `factor` is not greater than 1, as for
+    /// [`StackBuilder::growth_factor`](crate::StackBuilder::growth_factor).
+    /// Being `const`, this may be used to make a `static` policy.
+    pub const fn new(factor: f64) -> Self {
+        assert!(
+            factor > 1.0,
+            "second-stack: growth factor must be greater than 1"
+        );
+        Self(factor)
+    }
+}
+
+impl GrowthPolicy for Factor {
+    fn next_capacity(&self, current: usize, required: usize) -> usize {
+        let config = Config {
+            growth_factor: self.0,
+            ..Config::DEFAULT
+        };
+        config.next_capacity(current, required)
+    }
+}
+
+// Config is Copy and Debug, which a trait object is not
+#[derive(Copy, Clone)]
+pub(crate) struct Policy(pub &'static dyn GrowthPolicy);
+
+impl fmt::Debug for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("GrowthPolicy")
+    }
+}
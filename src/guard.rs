@@ -0,0 +1,281 @@
+use core::{
+    marker::PhantomData,
+    mem::{size_of, MaybeUninit},
+    ops::{Deref, DerefMut},
+    ptr, slice,
+};
+
+use crate::{scrub, DropStack, Stack, Writer};
+
+/// An uninit slice on a [`Stack`], returned by [`Stack::uninit_slice_guard`].
+/// The slice is given back to the stack when the guard is dropped.
+pub struct SliceGuard<'a, T> {
+    // None for the cases that do not use the stack
+    _restore: Option<DropStack<'a>>,
+    base: *mut MaybeUninit<T>,
+    len: usize,
+    // The guard holds the Stack exclusively so that no other allocation
+    // can be made from it until the guard is dropped.
+    _stack: PhantomData<&'a mut Stack>,
+}
+
+impl<'a, T> SliceGuard<'a, T> {
+    pub(crate) fn new(stack: &'a mut Stack, len: usize) -> Self {
+        // Special cases for ZST and len 0, which do not use the stack
+        // See also: 2ec61cda-e074-4b26-a9a5-a01b70706585
+        // See also: 26936c11-5b7c-472e-8f63-7922e63a5425
+        if size_of::<T>() == 0 || len == 0 {
+            return Self {
+                _restore: None,
+                base: ptr::NonNull::dangling().as_ptr(),
+                len,
+                _stack: PhantomData,
+            };
+        }
+
+        let location = &stack.0;
+        let (restore, (base, len)) = unsafe {
+            let stack = &mut *location.get();
+            stack.get_slice(location, len)
+        };
+
+        Self {
+            _restore: Some(restore),
+            base,
+            len,
+            _stack: PhantomData,
+        }
+    }
+}
+
+impl<T> Deref for SliceGuard<'_, T> {
+    type Target = [MaybeUninit<T>];
+    fn deref(&self) -> &Self::Target {
+        unsafe { slice::from_raw_parts(self.base, self.len) }
+    }
+}
+
+impl<T> DerefMut for SliceGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { slice::from_raw_parts_mut(self.base, self.len) }
+    }
+}
+
+/// Raw bytes on a [`Stack`], returned by [`Stack::reserve_raw`]. The bytes
+/// are committed until the guard is dropped, and then given back to the
+/// stack. This is a building block for abstractions over the stack which
+/// are not expressed as a closure.
+///
+/// Memory on a stack is given back in the reverse order that it was taken.
+/// The guard holds the stack exclusively to make sure of that, so nothing
+/// else can be taken from it until the guard is dropped.
+pub struct RawGuard<'a> {
+    // None for the case that does not use the stack
+    _restore: Option<DropStack<'a>>,
+    base: *mut u8,
+    len: usize,
+    _stack: PhantomData<&'a mut Stack>,
+}
+
+impl<'a> RawGuard<'a> {
+    pub(crate) fn new(stack: &'a mut Stack, len: usize, align: usize) -> Self {
+        assert!(
+            align.is_power_of_two(),
+            "second-stack: align must be a power of two"
+        );
+
+        // See also: 26936c11-5b7c-472e-8f63-7922e63a5425
+        if len == 0 {
+            return Self {
+                _restore: None,
+                base: ptr::without_provenance_mut(align),
+                len,
+                _stack: PhantomData,
+            };
+        }
+
+        let location = &stack.0;
+        let (restore, (base, len), _padding) = unsafe {
+            let stack = &mut *location.get();
+            stack.get_slice_aligned(location, len, align)
+        };
+
+        Self {
+            _restore: Some(restore),
+            base,
+            len,
+            _stack: PhantomData,
+        }
+    }
+
+    /// The start of the bytes, which is valid for reads and writes of
+    /// [`len`](RawGuard::len) bytes until the guard is dropped. The bytes
+    /// start out uninitialized.
+    pub fn as_ptr(&self) -> *mut u8 {
+        self.base
+    }
+
+    /// The number of bytes
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/// Gives back a slice taken by [`Stack::alloc_uninit_aligned`] when dropped.
+/// Guards must be dropped in the reverse order that they were taken.
+pub struct RestoreGuard<'a> {
+    // None for the cases that do not use the stack
+    _restore: Option<DropStack<'a>>,
+}
+
+/// A value on a [`Stack`], returned by [`Stack::boxed`]. The value is
+/// dropped and its memory given back to the stack when the box is dropped.
+pub struct StackBox<'a, T> {
+    slot: SliceGuard<'a, T>,
+}
+
+impl<'a, T> StackBox<'a, T> {
+    pub(crate) fn new(stack: &'a mut Stack, value: T) -> Self {
+        let mut slot = SliceGuard::new(stack, 1);
+        slot[0].write(value);
+        Self { slot }
+    }
+}
+
+impl<T> Deref for StackBox<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { self.slot[0].assume_init_ref() }
+    }
+}
+
+impl<T> DerefMut for StackBox<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { self.slot[0].assume_init_mut() }
+    }
+}
+
+impl<T> Drop for StackBox<'_, T> {
+    fn drop(&mut self) {
+        // The slot gives back the memory afterwards, as a field
+        unsafe { self.slot[0].assume_init_drop() }
+    }
+}
+
+/// Items on a [`Stack`], returned by [`Stack::acquire`]. The items are
+/// dropped and their memory given back to the stack when this is dropped.
+pub struct StackSlice<'a, T> {
+    // None for the cases that do not use the stack
+    _restore: Option<DropStack<'a>>,
+    base: *mut T,
+    len: usize,
+    _stack: PhantomData<&'a mut Stack>,
+}
+
+impl<'a, T> StackSlice<'a, T> {
+    pub(crate) fn new<I>(stack: &'a mut Stack, i: I) -> Self
+    where
+        I: Iterator<Item = T>,
+    {
+        let (lower, _upper) = i.size_hint();
+        let mut writer = Writer::with_capacity(&stack.0, lower);
+        for next in i {
+            writer.push(next);
+        }
+        writer.shrink_to_fit();
+
+        let (base, len, restore) = writer.into_raw_parts();
+        Self {
+            _restore: restore,
+            base,
+            len,
+            _stack: PhantomData,
+        }
+    }
+}
+
+impl<T> Deref for StackSlice<'_, T> {
+    type Target = [T];
+    fn deref(&self) -> &[T] {
+        unsafe { slice::from_raw_parts(self.base, self.len) }
+    }
+}
+
+impl<T> DerefMut for StackSlice<'_, T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        unsafe { slice::from_raw_parts_mut(self.base, self.len) }
+    }
+}
+
+impl<T> Drop for StackSlice<'_, T> {
+    fn drop(&mut self) {
+        // The restore gives back the memory afterwards, as a field
+        unsafe { ptr::drop_in_place(ptr::slice_from_raw_parts_mut(self.base, self.len)) }
+    }
+}
+
+impl<'a> RestoreGuard<'a> {
+    pub(crate) fn new(restore: Option<DropStack<'a>>) -> Self {
+        Self { _restore: restore }
+    }
+}
+
+/// A record of how much of a [`Stack`] was in use, returned by
+/// [`Stack::checkpoint`]. The stack may be used through the checkpoint,
+/// and [`rollback`](Checkpoint::rollback) gives back anything taken from
+/// it since, including the memory of guards which were leaked with
+/// `mem::forget`. Nothing is dropped.
+///
+/// If the stack has moved on to a new allocation since the checkpoint
+/// while leaked guards still held the old one, only the new allocation is
+/// given back. The old allocation is never freed, and its memory is leaked.
+pub struct Checkpoint<'a> {
+    stack: &'a mut Stack,
+    base: *mut u8,
+    len: usize,
+}
+
+impl<'a> Checkpoint<'a> {
+    pub(crate) fn new(stack: &'a mut Stack) -> Self {
+        let current = stack.0.get_mut();
+        let (base, len) = (current.base, current.len);
+        Self { stack, base, len }
+    }
+
+    /// Gives back everything taken from the stack since the checkpoint.
+    /// See the type's documentation for when the stack has since moved
+    /// on to a new allocation.
+    pub fn rollback(self) {
+        let current = self.stack.0.get_mut();
+        // Holding the stack exclusively means that nothing above the
+        // checkpoint can still be in use.
+        let len = if current.base == self.base {
+            self.len.min(current.len)
+        } else {
+            // The stack has moved on to a newer allocation, so all
+            // of it was taken after the checkpoint.
+            0
+        };
+        if current.len > len {
+            unsafe { scrub(current.base.add(len), current.len - len) };
+            current.len = len;
+        }
+    }
+}
+
+impl Deref for Checkpoint<'_> {
+    type Target = Stack;
+    fn deref(&self) -> &Stack {
+        self.stack
+    }
+}
+
+impl DerefMut for Checkpoint<'_> {
+    fn deref_mut(&mut self) -> &mut Stack {
+        self.stack
+    }
+}
@@ -0,0 +1,44 @@
+//! A global hook which is told whenever a stack's backing allocation
+//! grows or is freed, for correlating stack use with other metrics.
+
+use core::{
+    mem, ptr,
+    sync::atomic::{AtomicPtr, Ordering},
+};
+
+/// A change to the capacity of a stack's backing allocation, as passed
+/// to the hook set with [`set_growth_hook`]. When the allocation is freed,
+/// `new_capacity` is 0.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct GrowthEvent {
+    pub old_capacity: usize,
+    pub new_capacity: usize,
+}
+
+// A fn(GrowthEvent), or null when no hook is set
+static HOOK: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+
+/// Sets the function to call whenever any stack grows or frees its backing
+/// allocation, on any thread, or removes it with `None`. The hook is called
+/// while the stack is being changed, so it must not use second-stack itself.
+pub fn set_growth_hook(hook: Option<fn(GrowthEvent)>) {
+    let hook = match hook {
+        Some(hook) => hook as *mut (),
+        None => ptr::null_mut(),
+    };
+    HOOK.store(hook, Ordering::Release);
+}
+
+#[inline]
+pub(crate) fn notify(old_capacity: usize, new_capacity: usize) {
+    let hook = HOOK.load(Ordering::Acquire);
+    if hook.is_null() {
+        return;
+    }
+    // Safety: Only a fn(GrowthEvent) is ever stored
+    let hook = unsafe { mem::transmute::<*mut (), fn(GrowthEvent)>(hook) };
+    hook(GrowthEvent {
+        old_capacity,
+        new_capacity,
+    });
+}
@@ -1,17 +1,55 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+#![cfg_attr(feature = "allocator_api", feature(allocator_api))]
+
+extern crate alloc;
+
 mod allocation;
-use allocation::Allocation;
+mod builder;
+#[cfg(feature = "ffi")]
+mod ffi;
+mod growth;
+mod guard;
+#[cfg(feature = "instrument")]
+mod instrument;
+#[cfg(feature = "pool")]
+mod pool;
+mod scope;
+#[cfg(feature = "std")]
+mod threadlocal;
+#[cfg(feature = "tracing")]
+mod trace;
+mod writer;
+mod zeroable;
+pub use allocation::AllocError;
+use allocation::{slice_bytes, Allocation};
+pub use builder::StackBuilder;
+#[cfg(feature = "ffi")]
+pub use ffi::{second_stack_release, second_stack_reserve};
+pub use growth::{Doubling, Exact, Factor, GrowthPolicy};
+pub use guard::{Checkpoint, RawGuard, RestoreGuard, SliceGuard, StackBox, StackSlice};
+#[cfg(feature = "instrument")]
+pub use instrument::{set_growth_hook, GrowthEvent};
+pub use scope::{PartialInitGuard, StackScope};
+#[cfg(feature = "std")]
+pub use threadlocal::*;
+use writer::{PairWriter, Writer};
+pub use writer::{StackVec, StackWriter};
+pub use zeroable::Zeroable;
 
-use std::{
-    self,
+#[cfg(target_has_atomic = "ptr")]
+use alloc::sync::Arc;
+use alloc::{boxed::Box, rc::Rc, vec::Vec};
+use core::{
     cell::UnsafeCell,
-    mem::{size_of, MaybeUninit},
-    ptr, slice,
+    cmp::Ordering,
+    ffi::CStr,
+    fmt,
+    iter::Fuse,
+    mem::{self, align_of, size_of, size_of_val, MaybeUninit},
+    ops::ControlFlow,
+    ptr, slice, str,
 };
 
-thread_local!(
-    static THREAD_LOCAL: Stack = Stack::new()
-);
-
 /// A Stack that is managed separately from the threadlocal one.
 /// Typically, using the threadlocal APIs
 /// is encouraged because they enable sharing across libraries, where each
@@ -32,11 +70,224 @@ impl Drop for Stack {
     }
 }
 
+impl Default for Stack {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Stack {
-    pub fn new() -> Self {
+    /// Creates a new Stack. No memory is allocated until the first
+    /// slice is requested, which allows this to be used in a `const`
+    /// or `static` context.
+    pub const fn new() -> Self {
         Self(UnsafeCell::new(Allocation::null()))
     }
 
+    /// Creates a [`StackBuilder`], for a stack that grows differently
+    /// from the default.
+    pub fn builder() -> StackBuilder {
+        StackBuilder::default()
+    }
+
+    /// Creates a new Stack with room for at least `bytes` before it needs
+    /// to grow. The capacity is rounded up in the same way as when the stack
+    /// grows on demand. A capacity of 0 does not allocate.
+    pub fn with_capacity(bytes: usize) -> Self {
+        let stack = Self::new();
+        stack.reserve(bytes);
+        stack
+    }
+
+    /// Grows this stack so that at least `bytes` are available without
+    /// needing to grow again. This does nothing if a slice from this stack
+    /// is currently in use, or there is already enough room.
+    pub fn reserve(&self, bytes: usize) {
+        if bytes == 0 {
+            return;
+        }
+        let stack = unsafe { &mut *self.0.get() };
+        stack.reserve(bytes);
+    }
+
+    /// Frees the memory held by this stack, if no slices from it are in use.
+    /// Otherwise, does nothing. Useful for returning memory after a spike
+    /// in usage on a long-lived stack.
+    pub fn clear(&self) {
+        let stack = unsafe { &mut *self.0.get() };
+        if stack.len == 0 {
+            stack.try_dealloc();
+            // try_dealloc only resets the base
+            stack.capacity = 0;
+        }
+    }
+
+    /// Hands the memory held by this stack back to the OS, if no slices
+    /// from it are in use. Otherwise, does nothing. Unlike
+    /// [`clear`](Stack::clear), the allocation is kept, so the stack does not
+    /// need to grow again. This is for when the allocator would keep freed
+    /// memory around instead of returning it, such as after a one-time spike
+    /// on a long-lived worker.
+    #[cfg(all(unix, feature = "madvise"))]
+    pub fn release_to_os(&self) {
+        let stack = unsafe { &mut *self.0.get() };
+        stack.release_pages();
+    }
+
+    /// Takes the memory held by `other` in place of this stack's own, if
+    /// `other` has the larger capacity, so that a well-sized allocation can
+    /// outlive the stack which grew it. This stack's memory is freed in that
+    /// case, and otherwise `other`'s is. How this stack grows is unchanged.
+    ///
+    /// Neither stack may have slices in use, which is checked with
+    /// debug_assertions. Otherwise, nothing is taken.
+    pub fn merge(&self, mut other: Stack) {
+        let stack = unsafe { &mut *self.0.get() };
+        let other = other.0.get_mut();
+        debug_assert!(
+            stack.len == 0 && other.len == 0,
+            "second-stack: merge with a slice in use"
+        );
+        stack.adopt(other);
+    }
+
+    /// Touches each page of the memory held by this stack, if no slices from
+    /// it are in use. Otherwise, does nothing. The OS usually maps the pages
+    /// of a new allocation only as they are first written, which costs a page
+    /// fault each time. Calling this after growing the stack, and before a
+    /// section where latency matters, takes those faults up front. The pass
+    /// itself costs about as much as the faults it saves, plus a write to
+    /// each page, so only use it when the timing of the faults matters.
+    #[cfg(all(unix, feature = "prefault"))]
+    pub fn prefault(&self) {
+        let stack = unsafe { &mut *self.0.get() };
+        stack.prefault();
+    }
+
+    /// Replaces the memory held by this stack with a smaller allocation with
+    /// room for at least `bytes`, if no slices from it are in use. Otherwise,
+    /// does nothing. The capacity is rounded up in the same way as when the
+    /// stack grows on demand, and nothing is done if that is no smaller than
+    /// the current capacity. Useful for giving back most of the memory after
+    /// a spike in usage while keeping enough for the common case. See also
+    /// [`clear`](Stack::clear), which is the same as a size of 0.
+    pub fn reset_to_capacity(&self, bytes: usize) {
+        if bytes == 0 {
+            return self.clear();
+        }
+        let stack = unsafe { &mut *self.0.get() };
+        stack.shrink_to(bytes);
+    }
+
+    /// The number of bytes of this stack that are committed to slices
+    /// which are currently in use, including padding. When the stack has
+    /// grown while slices were in use, only those in the current
+    /// allocation are counted.
+    pub fn bytes_in_use(&self) -> usize {
+        self.allocation().len
+    }
+
+    /// The number of bytes of this stack that are in use. The same as
+    /// [`bytes_in_use`](Stack::bytes_in_use).
+    pub fn len(&self) -> usize {
+        self.bytes_in_use()
+    }
+
+    /// Whether nothing is in use, as when every slice has been given back.
+    /// The opposite of [`is_borrowed`](Stack::is_borrowed).
+    pub fn is_empty(&self) -> bool {
+        !self.is_borrowed()
+    }
+
+    /// Whether any slices from this stack are currently in use, judging by
+    /// [`bytes_in_use`](Stack::bytes_in_use). Slices which take no memory
+    /// are not counted, and neither are slices left in an earlier allocation
+    /// once the stack has grown and the slices after them are given back.
+    pub fn is_borrowed(&self) -> bool {
+        self.allocation().len != 0
+    }
+
+    /// The number of bytes this stack can hold before it needs to grow.
+    pub fn capacity(&self) -> usize {
+        self.allocation().capacity
+    }
+
+    /// The number of bytes that can be taken from this stack before it
+    /// needs to grow. Alignment may use some of these.
+    pub fn remaining_bytes(&self) -> usize {
+        self.allocation().remaining_bytes()
+    }
+
+    /// The largest number of bytes that have been in use at once in
+    /// a single allocation of this stack over its lifetime.
+    #[cfg(feature = "stats")]
+    pub fn peak_bytes(&self) -> usize {
+        self.allocation().peak
+    }
+
+    /// The number of slices and scopes from this stack which are currently
+    /// live, for checking that code gives back everything that it takes.
+    /// A scope counts once for each allocation of the stack that it uses.
+    /// Slices which take no memory, and slices on the heap, are not counted.
+    #[cfg(feature = "stats")]
+    pub fn live_scopes(&self) -> usize {
+        self.allocation().live
+    }
+
+    /// Calls `f`, and returns its result along with the largest number of
+    /// bytes of this stack that were in use at once during the call, beyond
+    /// those already in use when it started. Useful for attributing usage
+    /// of the stack to particular code paths.
+    ///
+    /// Like [`peak_bytes`](Stack::peak_bytes), this is measured within a
+    /// single allocation. If the stack grows during `f`, the bytes in use
+    /// before the call are counted too, so reserve enough beforehand for an
+    /// exact figure.
+    #[cfg(feature = "stats")]
+    pub fn measure<F, R>(&self, f: F) -> (R, usize)
+    where
+        F: FnOnce() -> R,
+    {
+        // Puts back the lifetime peak, even if f panics
+        struct Restore<'a> {
+            location: &'a UnsafeCell<Allocation>,
+            peak: usize,
+        }
+
+        impl Drop for Restore<'_> {
+            fn drop(&mut self) {
+                let stack = unsafe { &mut *self.location.get() };
+                stack.peak = stack.peak.max(self.peak);
+            }
+        }
+
+        let stack = unsafe { &mut *self.0.get() };
+        let (base, start) = (stack.base, stack.len);
+        let restore = Restore {
+            location: &self.0,
+            peak: mem::replace(&mut stack.peak, start),
+        };
+        let result = f();
+        let stack = self.allocation();
+        let used = if stack.base == base {
+            stack.peak - start
+        } else {
+            stack.peak
+        };
+        drop(restore);
+        (result, used)
+    }
+
+    fn is_above_heap_threshold<T>(&self, len: usize) -> bool {
+        slice_bytes::<T>(len) > self.allocation().config.heap_threshold
+    }
+
+    fn allocation(&self) -> &Allocation {
+        // Safety: The allocation is only modified by the methods of Stack,
+        // and none of them can be running while this reference is in use.
+        unsafe { &*self.0.get() }
+    }
+
     /// Place a potentially very large value on this stack.
     pub fn uninit<T, R, F>(&self, f: F) -> R
     where
@@ -48,173 +299,1234 @@ impl Stack {
         self.uninit_slice(1, |slice| f(&mut slice[0]))
     }
 
-    /// Allocates an uninit slice from this stack.
+    /// Moves `value` onto this stack and calls `f` with it, so that a large
+    /// value can be kept off the call stack. The value is dropped after `f`
+    /// returns, or if `f` panics.
+    pub fn with<T, F, R>(&self, value: T, f: F) -> R
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        let mut writer = Writer::with_capacity(&self.0, 1);
+        writer.push(value);
+        f(&mut writer.as_mut_slice()[0])
+    }
+
+    /// Allocates an uninit array of `N` items from this stack. This is
+    /// for small temporaries of a fixed size, where indexing an array
+    /// is more convenient than a slice.
+    pub fn uninit_array<T, const N: usize, F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut [MaybeUninit<T>; N]) -> R,
+    {
+        // Also covers N == 0, for which uninit_slice gives an empty slice
+        self.uninit_slice(N, |slice| {
+            let array = slice.try_into().expect("the slice has len N");
+            f(array)
+        })
+    }
+
+    /// Like [`uninit`](Stack::uninit), but returns an error instead of
+    /// aborting the process if the stack needs to grow and the memory
+    /// cannot be allocated. `f` is not called when this fails.
+    pub fn try_uninit<T, R, F>(&self, f: F) -> Result<R, AllocError>
+    where
+        F: FnOnce(&mut MaybeUninit<T>) -> R,
+    {
+        self.try_uninit_slice(1, |slice| f(&mut slice[0]))
+    }
+
+    /// Allocates an uninit slice from this stack. A slice larger than the
+    /// stack's [`heap_threshold`](StackBuilder::heap_threshold) is allocated
+    /// on the heap instead, and freed when `f` returns.
+    ///
+    /// The slice is aligned for `T`, including a type with `#[repr(align)]`
+    /// such as for SIMD lanes. For more alignment than that, see
+    /// [`uninit_slice_aligned`](Stack::uninit_slice_aligned).
     pub fn uninit_slice<T, F, R>(&self, len: usize, f: F) -> R
     where
         F: FnOnce(&mut [MaybeUninit<T>]) -> R,
+    {
+        self.uninit_slice_padding(len, |slice, _padding| f(slice))
+    }
+
+    /// Allocates two uninit slices from this stack at once, such as for
+    /// keys and values, which are given back when `f` returns. The slices
+    /// are placed back-to-back, each aligned for its own type.
+    pub fn uninit_slice2<A, B, F, R>(&self, len_a: usize, len_b: usize, f: F) -> R
+    where
+        F: FnOnce(&mut [MaybeUninit<A>], &mut [MaybeUninit<B>]) -> R,
+    {
+        // A slice which goes to the heap needs no room on the stack
+        let on_stack = |len, above_threshold| if above_threshold { 0 } else { len };
+        let stack_a = on_stack(len_a, self.is_above_heap_threshold::<A>(len_a));
+        let stack_b = on_stack(len_b, self.is_above_heap_threshold::<B>(len_b));
+        unsafe {
+            let stack = &mut *self.0.get();
+            stack.reserve_pair::<A, B>(stack_a, stack_b);
+        }
+        self.uninit_slice(len_a, |a| self.uninit_slice(len_b, |b| f(a, b)))
+    }
+
+    /// Allocates an uninit slice of up to `len` items from this stack, for
+    /// when only an upper bound on the length is known ahead of time. `init`
+    /// fills a prefix of the slice and returns its length. The rest of the
+    /// slice is given back to the stack, and `f` is given only the prefix,
+    /// so that allocations made within `f` can reuse the memory that was
+    /// not needed. Only the prefix is counted as in use.
+    ///
+    /// Filling the slice and using the prefix are two closures, rather than
+    /// one returning the length along with its result, because the tail can
+    /// only be given back once `init` has returned. Allocations made from
+    /// within `f` can then reuse it, which one closure could not allow.
+    ///
+    /// Panics if `init` returns more than `len`.
+    pub fn uninit_slice_commit<T, G, F, R>(&self, len: usize, init: G, f: F) -> R
+    where
+        G: FnOnce(&mut [MaybeUninit<T>]) -> usize,
+        F: FnOnce(&mut [MaybeUninit<T>]) -> R,
+    {
+        self.uninit_slice(len, |slice| {
+            let used = init(slice);
+            assert!(
+                used <= slice.len(),
+                "second-stack: committed more than the slice's len"
+            );
+            let (prefix, tail) = slice.split_at_mut(used);
+
+            // The tail can only be given back when nothing is above it,
+            // which is not the case for a slice on the heap.
+            let tail_bytes = size_of_val(tail);
+            let stack = unsafe { &mut *self.0.get() };
+            let top = stack.base.wrapping_add(stack.len);
+            if tail_bytes != 0 && top == tail.as_mut_ptr_range().end as *mut u8 {
+                stack.give_back_to(stack.len - tail_bytes);
+            }
+
+            f(prefix)
+        })
+    }
+
+    /// Like [`uninit_slice`](Stack::uninit_slice), but returns an error
+    /// instead of aborting the process if the stack needs to grow and the
+    /// memory cannot be allocated. Nothing is taken from the stack when
+    /// this fails.
+    pub fn try_uninit_slice<T, F, R>(&self, len: usize, f: F) -> Result<R, AllocError>
+    where
+        F: FnOnce(&mut [MaybeUninit<T>]) -> R,
+    {
+        // See also: 2ec61cda-e074-4b26-a9a5-a01b70706585
+        // See also: 26936c11-5b7c-472e-8f63-7922e63a5425
+        if size_of::<T>() == 0 || len == 0 {
+            return Ok(self.uninit_slice(len, f));
+        }
+
+        if self.is_above_heap_threshold::<T>(len) {
+            let mut heap = Vec::new();
+            heap.try_reserve_exact(len).map_err(|_| AllocError)?;
+            unsafe { heap.set_len(len) };
+            let mut heap = HeapSlice(heap);
+            return Ok(f(&mut heap.0));
+        }
+
+        let (_restore, (ptr, len), _padding) = unsafe {
+            let stack = &mut *self.0.get();
+            stack.try_get_slice_aligned(&self.0, len, align_of::<T>())?
+        };
+
+        let slice = unsafe { slice::from_raw_parts_mut(ptr, len) };
+
+        Ok(f(slice))
+    }
+
+    /// Allocates an uninit slice from this stack which is given back when
+    /// the returned guard is dropped. This is an alternative to
+    /// [`uninit_slice`](Stack::uninit_slice) for when a closure is awkward.
+    /// The stack is borrowed until the guard is dropped, so it cannot be
+    /// used for anything else in the meantime.
+    pub fn uninit_slice_guard<T>(&mut self, len: usize) -> SliceGuard<'_, T> {
+        SliceGuard::new(self, len)
+    }
+
+    /// Takes `bytes` bytes from this stack, aligned to `align`, which stay
+    /// committed until the returned guard is dropped. This is the raw form
+    /// of [`uninit_slice_guard`](Stack::uninit_slice_guard). The stack is
+    /// borrowed until the guard is dropped, so it cannot be used for
+    /// anything else in the meantime.
+    ///
+    /// Panics if `align` is not a power of two.
+    pub fn reserve_raw(&mut self, bytes: usize, align: usize) -> RawGuard<'_> {
+        RawGuard::new(self, bytes, align)
+    }
+
+    /// Records how much of this stack is in use, so that anything taken
+    /// from it through the returned [`Checkpoint`] can be given back at once
+    /// with [`Checkpoint::rollback`], such as when a speculative parse fails.
+    pub fn checkpoint(&mut self) -> Checkpoint<'_> {
+        Checkpoint::new(self)
+    }
+
+    /// Moves a value onto this stack, where it stays until the returned
+    /// box is dropped. This is an alternative to [`uninit`](Stack::uninit)
+    /// for when a closure is awkward. The stack is borrowed until the box
+    /// is dropped, so it cannot be used for anything else in the meantime.
+    pub fn boxed<T>(&mut self, value: T) -> StackBox<'_, T> {
+        StackBox::new(self, value)
+    }
+
+    /// Buffers an iterator to a slice on this stack, where the items stay
+    /// until the returned [`StackSlice`] is dropped. This is an alternative
+    /// to [`buffer`](Stack::buffer) for when a closure is awkward, such as to
+    /// return the items to a caller. The stack is borrowed until the slice is
+    /// dropped, so it cannot be used for anything else in the meantime. There
+    /// is no threadlocal version, since the threadlocal stack cannot be
+    /// borrowed that way.
+    pub fn acquire<T, I>(&mut self, i: I) -> StackSlice<'_, T>
+    where
+        I: Iterator<Item = T>,
+    {
+        StackSlice::new(self, i)
+    }
+
+    /// Allocates a slice from this stack, initializing each element with
+    /// the result of `init(index)`. The elements are dropped after `f`
+    /// returns, or if `init` panics partway through.
+    pub fn uninit_slice_with<T, G, F, R>(&self, len: usize, mut init: G, f: F) -> R
+    where
+        G: FnMut(usize) -> T,
+        F: FnOnce(&mut [T]) -> R,
+    {
+        let mut writer = Writer::with_capacity(&self.0, len);
+        for index in 0..len {
+            writer.push(init(index));
+        }
+        f(writer.as_mut_slice())
+    }
+
+    /// Allocates a slice from this stack with all bytes set to zero. This is
+    /// cheaper than zeroing the result of [`uninit_slice`](Stack::uninit_slice)
+    /// element by element because it is a single memset over the slice.
+    /// The elements are not dropped.
+    pub fn uninit_slice_zeroed<T, F, R>(&self, len: usize, f: F) -> R
+    where
+        T: Zeroable,
+        F: FnOnce(&mut [T]) -> R,
+    {
+        self.uninit_slice(len, |slice| unsafe {
+            ptr::write_bytes(slice.as_mut_ptr(), 0, slice.len());
+            f(&mut *(slice as *mut [MaybeUninit<T>] as *mut [T]))
+        })
+    }
+
+    /// Allocates an uninit slice from this stack, with its start aligned to
+    /// at least `align` bytes. This is for buffers that need a greater
+    /// alignment than `T` has, such as for SIMD or DMA.
+    ///
+    /// `align` must be a power of two.
+    pub fn uninit_slice_aligned<T, F, R>(&self, len: usize, align: usize, f: F) -> R
+    where
+        F: FnOnce(&mut [MaybeUninit<T>]) -> R,
+    {
+        debug_assert!(
+            align.is_power_of_two(),
+            "second-stack: align must be a power of two"
+        );
+        let align = align.max(align_of::<T>());
+
+        // Nothing is allocated, but the pointer is still aligned as asked.
+        // See also: 2ec61cda-e074-4b26-a9a5-a01b70706585
+        // See also: 26936c11-5b7c-472e-8f63-7922e63a5425
+        if size_of::<T>() == 0 || len == 0 {
+            let ptr = ptr::without_provenance_mut::<MaybeUninit<T>>(align);
+            return f(unsafe { slice::from_raw_parts_mut(ptr, len) });
+        }
+
+        let (_restore, (ptr, len), _padding) = unsafe {
+            let stack = &mut *self.0.get();
+            stack.get_slice_aligned(&self.0, len, align)
+        };
+
+        let slice = unsafe { slice::from_raw_parts_mut(ptr, len) };
+
+        f(slice)
+    }
+
+    /// Allocates an uninit slice from this stack, aligned to at least
+    /// `align`, and returns it along with the guard that gives it back.
+    /// This is a building block for keeping several slices of different
+    /// types alive at once with control over when each is given back.
+    /// [`StackScope`] is a safe alternative.
+    ///
+    /// `align` must be a power of two.
+    ///
+    /// # Safety
+    /// Guards must be dropped in the reverse order that they were taken,
+    /// and each must be dropped before anything taken from this stack
+    /// before it is given back, such as by returning from a closure. The
+    /// slice must not be used after its guard is dropped.
+    #[allow(clippy::mut_from_ref)]
+    pub unsafe fn alloc_uninit_aligned<T>(
+        &self,
+        len: usize,
+        align: usize,
+    ) -> (RestoreGuard<'_>, &mut [MaybeUninit<T>]) {
+        debug_assert!(
+            align.is_power_of_two(),
+            "second-stack: align must be a power of two"
+        );
+        let align = align.max(align_of::<T>());
+
+        // See also: 2ec61cda-e074-4b26-a9a5-a01b70706585
+        // See also: 26936c11-5b7c-472e-8f63-7922e63a5425
+        if size_of::<T>() == 0 || len == 0 {
+            let ptr = ptr::without_provenance_mut::<MaybeUninit<T>>(align);
+            return (RestoreGuard::new(None), slice::from_raw_parts_mut(ptr, len));
+        }
+
+        let stack = &mut *self.0.get();
+        let (restore, (ptr, len), _padding) = stack.get_slice_aligned(&self.0, len, align);
+        (
+            RestoreGuard::new(Some(restore)),
+            slice::from_raw_parts_mut(ptr, len),
+        )
+    }
+
+    /// Allocates `len` bytes from this stack, aligned to `align`, and passes
+    /// `f` a raw pointer to them along with `len`. This is for scratch space
+    /// to hand to a C function which writes into it.
+    ///
+    /// The pointer is valid for reads and writes of `len` bytes until `f`
+    /// returns, when the bytes are given back to the stack. It must not be
+    /// kept or used after that. The bytes are uninit until written. When
+    /// `len` is 0, the pointer is aligned but must not be dereferenced.
+    ///
+    /// `align` must be a power of two.
+    pub fn with_raw_bytes<F, R>(&self, len: usize, align: usize, f: F) -> R
+    where
+        F: FnOnce(*mut u8, usize) -> R,
+    {
+        self.uninit_slice_aligned(len, align, |bytes: &mut [MaybeUninit<u8>]| {
+            f(bytes.as_mut_ptr() as *mut u8, bytes.len())
+        })
+    }
+
+    /// Allocates an uninit slice from this stack, and also passes to `f`
+    /// the number of bytes of padding that were inserted before the slice
+    /// to satisfy the alignment of `T`. This is 0 when the top of the stack
+    /// was already aligned, and is useful for ordering allocations of
+    /// mixed types to minimize waste.
+    pub fn uninit_slice_padding<T, F, R>(&self, len: usize, f: F) -> R
+    where
+        F: FnOnce(&mut [MaybeUninit<T>], usize) -> R,
     {
         // Special case for ZST that disregards the rest of the code,
         // so that none of that code need account for ZSTs.
         // The reason this is convenient is that a ZST may use
         // the stack without bumping the pointer, which will
         // lead other code to free that memory while still in-use.
+        // A ZST slice of any len can live at a dangling pointer.
         // See also: 2ec61cda-e074-4b26-a9a5-a01b70706585
         // There may be other issues also.
-        if std::mem::size_of::<T>() == 0 {
-            let mut tmp = Vec::<T>::with_capacity(len);
-            // We do need to take a slice here, because suprisingly
-            // tmp.capacity() returns 18446744073709551615
-            let slice = &mut tmp.spare_capacity_mut()[..len];
-            return f(slice);
+        if size_of::<T>() == 0 {
+            let ptr = ptr::NonNull::<MaybeUninit<T>>::dangling().as_ptr();
+            return f(unsafe { slice::from_raw_parts_mut(ptr, len) }, 0);
         }
 
         // Required for correctness
         // See also: 26936c11-5b7c-472e-8f63-7922e63a5425
         if len == 0 {
-            return f(&mut []);
+            return f(&mut [], 0);
+        }
+
+        // A slice this large would leave the stack holding far more memory
+        // than usual, so it is allocated just for this call instead.
+        if self.is_above_heap_threshold::<T>(len) {
+            let mut heap = Vec::with_capacity(len);
+            unsafe { heap.set_len(len) };
+            let mut heap = HeapSlice(heap);
+            return f(&mut heap.0, 0);
         }
 
         // Get the new slice, and the old allocation to
         // restore once the function is finished running.
-        let (_restore, (ptr, len)) = unsafe {
+        let (_restore, (ptr, len), padding) = unsafe {
             let stack = &mut *self.0.get();
-            stack.get_slice(&self.0, len)
+            stack.get_slice_padded(&self.0, len)
         };
 
-        let slice = unsafe { slice::from_raw_parts_mut(ptr as *mut MaybeUninit<T>, len) };
+        let slice = unsafe { slice::from_raw_parts_mut(ptr, len) };
 
-        f(slice)
+        f(slice, padding)
     }
 
     /// Buffers an iterator to a slice on this stack and gives temporary access to that slice.
     /// Do not use with an unbounded iterator, because this will eventually run out of memory and panic.
+    ///
+    /// The iterator is not polled again once it returns `None`, here or in
+    /// any of the other methods which buffer an iterator.
+    ///
+    /// The slice is an ordinary `&mut [T]`, so it may be split with
+    /// `split_at_mut` or `chunks_mut` to hand parts of it to different
+    /// helpers, which may themselves use the stack.
     pub fn buffer<T, F, R, I>(&self, i: I, f: F) -> R
     where
         I: Iterator<Item = T>,
         F: FnOnce(&mut [T]) -> R,
     {
-        // Special case for ZST
-        if size_of::<T>() == 0 {
-            let mut v: Vec<_> = i.collect();
-            return f(&mut v);
-        }
-
-        // Data goes in a struct in case user code panics.
-        // User code includes Iterator::next, FnOnce, and Drop::drop
-        struct Writer<'a, T> {
-            restore: Option<DropStack<'a>>,
-            base: *mut T,
-            len: usize,
-            capacity: usize,
-        }
-
-        impl<T> Writer<'_, T> {
-            unsafe fn write(&mut self, item: T) {
-                self.base.add(self.len).write(item);
-                self.len += 1;
-            }
-
-            fn try_reuse(&mut self, stack: &mut Allocation) -> bool {
-                if let Some(prev) = &self.restore {
-                    if prev.restore.ref_eq(stack) {
-                        // If we are already are using this stack, we know the
-                        // end ptr is already aligned. To double in size,
-                        // we would need as many bytes as there are currently
-                        // and do not need to align
-                        let required_bytes = size_of::<T>() * self.capacity;
-
-                        if stack.remaining_bytes() >= required_bytes {
-                            stack.len += required_bytes;
-                            self.capacity *= 2;
-                            return true;
-                        }
-                    }
-                }
-                false
+        self.buffer_with_capacity(i, 0, f)
+    }
+
+    /// Like [`buffer`](Stack::buffer), but a panic in the iterator or in `f`
+    /// is caught and returned as an error instead of unwinding further. Any
+    /// items that were buffered are dropped and the stack is restored before
+    /// this returns.
+    ///
+    /// The iterator and `f` are not required to be [`UnwindSafe`], as if they
+    /// were wrapped in [`AssertUnwindSafe`]. By calling this, the caller takes
+    /// responsibility for unwind safety: anything they capture which a panic
+    /// could leave in a broken state must not be relied upon afterwards.
+    ///
+    /// [`UnwindSafe`]: std::panic::UnwindSafe
+    /// [`AssertUnwindSafe`]: std::panic::AssertUnwindSafe
+    #[cfg(feature = "std")]
+    pub fn catch_buffer<T, F, R, I>(&self, i: I, f: F) -> std::thread::Result<R>
+    where
+        I: Iterator<Item = T>,
+        F: FnOnce(&mut [T]) -> R,
+    {
+        // The stack is left as it was before the call when unwinding, so it
+        // is fine to keep using it after a caught panic.
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+        catch_unwind(AssertUnwindSafe(|| self.buffer(i, f)))
+    }
+
+    /// Like [`buffer`](Stack::buffer), but for `Copy` items from an iterator
+    /// of known length, such as from a slice. Room for all of the items is
+    /// made up front, so there is no check for room as each is written.
+    ///
+    /// If the iterator gives fewer items than its `len`, the slice is
+    /// shorter. Any items beyond its `len` are not buffered.
+    pub fn buffer_copy<T, F, R, I>(&self, i: I, f: F) -> R
+    where
+        T: Copy,
+        I: ExactSizeIterator<Item = T>,
+        F: FnOnce(&mut [T]) -> R,
+    {
+        self.uninit_slice(i.len(), |slots| {
+            let mut len = 0;
+            for (slot, item) in slots.iter_mut().zip(i) {
+                slot.write(item);
+                len += 1;
             }
+            let items = &mut slots[..len];
+            // Safety: The first len items were written
+            f(unsafe { &mut *(items as *mut [MaybeUninit<T>] as *mut [T]) })
+        })
+    }
+
+    /// Copies `src` to a slice on this stack and gives temporary access to
+    /// that slice, such as to sort or change it without touching the original.
+    pub fn copy_slice<T, F, R>(&self, src: &[T], f: F) -> R
+    where
+        T: Copy,
+        F: FnOnce(&mut [T]) -> R,
+    {
+        self.uninit_slice(src.len(), |slots| unsafe {
+            // The slots are a new region, so can't overlap src
+            ptr::copy_nonoverlapping(src.as_ptr(), slots.as_mut_ptr() as *mut T, src.len());
+            f(&mut *(slots as *mut [MaybeUninit<T>] as *mut [T]))
+        })
+    }
+
+    /// Like [`buffer`](Stack::buffer), but makes room for at least
+    /// `cap_hint` items up front. This is for when the number of items is
+    /// known, but the iterator's `size_hint` does not say so. If there turn
+    /// out to be more items, the slice grows as usual.
+    pub fn buffer_with_capacity<T, F, R, I>(&self, i: I, cap_hint: usize, f: F) -> R
+    where
+        I: Iterator<Item = T>,
+        F: FnOnce(&mut [T]) -> R,
+    {
+        // A ZST is handled by the writer, which counts the items
+        // without storing them anywhere.
+        // Only the lower bound of the size hint is used, because the upper
+        // bound of something like a filtered iterator may be far larger than
+        // the number of items actually produced.
+        let (lower, _upper) = i.size_hint();
+        let mut writer = Writer::with_capacity(&self.0, lower.max(cap_hint));
+        for next in i {
+            writer.push(next);
         }
 
-        impl<T> Drop for Writer<'_, T> {
-            fn drop(&mut self) {
-                unsafe {
-                    for i in 0..self.len {
-                        self.base.add(i).drop_in_place()
-                    }
-                }
+        // Growing by doubling may have over-allocated. The space beyond the
+        // items is returned so that it may be used by nested allocations in f.
+        writer.shrink_to_fit();
+
+        f(writer.as_mut_slice())
+    }
+
+    /// Buffers the items from the start of an iterator for which `pred`
+    /// returns true, stopping at the first for which it returns false, and
+    /// gives temporary access to the slice. As with `Iterator::take_while`,
+    /// the item which stops it is taken from the iterator and dropped before
+    /// `f` is called. Pass `i.by_ref()` to keep using the iterator afterwards.
+    pub fn buffer_take_while<T, P, F, R, I>(&self, i: I, pred: P, f: F) -> R
+    where
+        P: FnMut(&T) -> bool,
+        I: Iterator<Item = T>,
+        F: FnOnce(&mut [T]) -> R,
+    {
+        self.buffer(i.take_while(pred), f)
+    }
+
+    /// Buffers the items of an iterator after skipping those at the start for
+    /// which `pred` returns true, and gives temporary access to the slice. The
+    /// skipped items are dropped as they are skipped. The first item for which
+    /// `pred` returns false is the first in the slice.
+    pub fn buffer_skip_while<T, P, F, R, I>(&self, i: I, pred: P, f: F) -> R
+    where
+        P: FnMut(&T) -> bool,
+        I: Iterator<Item = T>,
+        F: FnOnce(&mut [T]) -> R,
+    {
+        self.buffer(i.skip_while(pred), f)
+    }
+
+    /// Buffers the items of each of the inner iterators to one slice on this
+    /// stack, in order, as with `buffer(i.flatten(), f)`. Room is made for
+    /// the items of each inner iterator according to its size hint. If any of
+    /// the iterators panics, the items buffered so far are dropped.
+    pub fn buffer_flatten<T, F, R, I, J>(&self, i: I, f: F) -> R
+    where
+        I: Iterator<Item = J>,
+        J: IntoIterator<Item = T>,
+        F: FnOnce(&mut [T]) -> R,
+    {
+        let mut writer = Writer::new(&self.0);
+        for inner in i {
+            let inner = inner.into_iter();
+            writer.reserve(inner.size_hint().0);
+            for next in inner {
+                writer.push(next);
             }
         }
+        writer.shrink_to_fit();
 
-        unsafe {
-            let mut writer = Writer {
-                restore: None,
-                base: ptr::null_mut(),
-                capacity: 0,
-                len: 0,
+        f(writer.as_mut_slice())
+    }
+
+    /// Buffers an iterator to a slice on this stack while folding `step`
+    /// over each item as it is written, starting from `init`, and gives
+    /// temporary access to the slice along with the final state. This saves
+    /// a second pass over the slice for something like a sum. If the
+    /// iterator or `step` panics, the state and items are dropped.
+    pub fn buffer_scan<T, St, S, F, R, I>(&self, i: I, init: St, mut step: S, f: F) -> R
+    where
+        S: FnMut(&mut St, &T),
+        I: Iterator<Item = T>,
+        F: FnOnce(&mut [T], St) -> R,
+    {
+        let (lower, _upper) = i.size_hint();
+        let mut writer = Writer::with_capacity(&self.0, lower);
+        let mut state = init;
+        for next in i {
+            step(&mut state, &next);
+            writer.push(next);
+        }
+        writer.shrink_to_fit();
+
+        f(writer.as_mut_slice(), state)
+    }
+
+    /// Buffers an iterator to a slice on this stack, pairing each item with
+    /// its position in the iterator, and gives temporary access to that
+    /// slice. The positions stay with their items if the slice is reordered.
+    pub fn buffer_enumerate<T, F, R, I>(&self, i: I, f: F) -> R
+    where
+        I: Iterator<Item = T>,
+        F: FnOnce(&mut [(usize, T)]) -> R,
+    {
+        self.buffer(i.enumerate(), f)
+    }
+
+    /// Buffers an iterator to a slice on this stack, finding the item with
+    /// the largest key while doing so, and gives temporary access to that
+    /// slice along with the index of that item. The index is `None` when the
+    /// iterator is empty. As with `Iterator::max_by_key`, the last of several
+    /// equally large items is chosen.
+    pub fn buffer_max_by_key<T, K, P, F, R, I>(&self, i: I, key: P, f: F) -> R
+    where
+        K: Ord,
+        P: FnMut(&T) -> K,
+        I: Iterator<Item = T>,
+        F: FnOnce(&mut [T], Option<usize>) -> R,
+    {
+        self.buffer_extremum_by_key(i, key, |new, best| new >= best, f)
+    }
+
+    /// Like [`buffer_max_by_key`](Stack::buffer_max_by_key), but for the
+    /// item with the smallest key. As with `Iterator::min_by_key`, the first
+    /// of several equally small items is chosen.
+    pub fn buffer_min_by_key<T, K, P, F, R, I>(&self, i: I, key: P, f: F) -> R
+    where
+        K: Ord,
+        P: FnMut(&T) -> K,
+        I: Iterator<Item = T>,
+        F: FnOnce(&mut [T], Option<usize>) -> R,
+    {
+        self.buffer_extremum_by_key(i, key, |new, best| new < best, f)
+    }
+
+    fn buffer_extremum_by_key<T, K, P, F, R, I>(
+        &self,
+        i: I,
+        mut key: P,
+        replaces: fn(&K, &K) -> bool,
+        f: F,
+    ) -> R
+    where
+        P: FnMut(&T) -> K,
+        I: Iterator<Item = T>,
+        F: FnOnce(&mut [T], Option<usize>) -> R,
+    {
+        let (lower, _upper) = i.size_hint();
+        let mut writer = Writer::with_capacity(&self.0, lower);
+        // The key of the chosen item so far, and its index
+        let mut best: Option<(K, usize)> = None;
+        for next in i {
+            let k = key(&next);
+            if best.as_ref().is_none_or(|(b, _)| replaces(&k, b)) {
+                best = Some((k, writer.len()));
+            }
+            writer.push(next);
+        }
+        writer.shrink_to_fit();
+
+        f(writer.as_mut_slice(), best.map(|(_, index)| index))
+    }
+
+    /// Like [`buffer`](Stack::buffer), but sorts the slice before giving
+    /// access to it. The sort is unstable, as with `slice::sort_unstable`.
+    pub fn buffer_sorted<T, F, R, I>(&self, i: I, f: F) -> R
+    where
+        T: Ord,
+        I: Iterator<Item = T>,
+        F: FnOnce(&mut [T]) -> R,
+    {
+        self.buffer(i, |items| {
+            items.sort_unstable();
+            f(items)
+        })
+    }
+
+    /// Like [`buffer_sorted`](Stack::buffer_sorted), but sorts with the
+    /// comparator `compare`. If `compare` panics, the items are still dropped.
+    pub fn buffer_sorted_by<T, C, F, R, I>(&self, i: I, compare: C, f: F) -> R
+    where
+        I: Iterator<Item = T>,
+        C: FnMut(&T, &T) -> Ordering,
+        F: FnOnce(&mut [T]) -> R,
+    {
+        self.buffer(i, |items| {
+            items.sort_unstable_by(compare);
+            f(items)
+        })
+    }
+
+    /// Like [`buffer`](Stack::buffer), but leaves out consecutive repeated
+    /// items, as with `Vec::dedup`. The items left out are dropped as they
+    /// are found, so they never take space on the stack.
+    pub fn buffer_dedup<T, F, R, I>(&self, i: I, f: F) -> R
+    where
+        T: PartialEq,
+        I: Iterator<Item = T>,
+        F: FnOnce(&mut [T]) -> R,
+    {
+        let (lower, _upper) = i.size_hint();
+        let mut writer = Writer::with_capacity(&self.0, lower);
+        for next in i {
+            if writer.as_slice().last() != Some(&next) {
+                writer.push(next);
+            }
+        }
+        writer.shrink_to_fit();
+
+        f(writer.as_mut_slice())
+    }
+
+    /// Like [`buffer_dedup`](Stack::buffer_dedup), but compares the keys
+    /// returned by `key`, as with `Vec::dedup_by_key`.
+    pub fn buffer_dedup_by_key<T, K, G, F, R, I>(&self, i: I, mut key: G, f: F) -> R
+    where
+        K: PartialEq,
+        I: Iterator<Item = T>,
+        G: FnMut(&mut T) -> K,
+        F: FnOnce(&mut [T]) -> R,
+    {
+        let (lower, _upper) = i.size_hint();
+        let mut writer = Writer::with_capacity(&self.0, lower);
+        for mut next in i {
+            let repeated = match writer.as_mut_slice().last_mut() {
+                Some(last) => key(last) == key(&mut next),
+                None => false,
             };
+            if !repeated {
+                writer.push(next);
+            }
+        }
+        writer.shrink_to_fit();
+
+        f(writer.as_mut_slice())
+    }
 
-            for next in i {
-                if writer.capacity == writer.len {
-                    let stack = &mut *self.0.get();
-
-                    // First try to use the same stack, but if that fails
-                    // copy over to the upsized stack
-                    if !writer.try_reuse(stack) {
-                        // This will always be a different allocation, otherwise
-                        // try_reuse would have succeeded
-                        let (restore, (base, capacity)) =
-                            stack.get_slice(&self.0, (writer.len * 2).max(1));
-
-                        // Check for 0 is to avoid copy from null ptr (miri violation)
-                        if writer.len != 0 {
-                            ptr::copy_nonoverlapping(writer.base, base, writer.len);
-                        }
-
-                        // This attempts to restore the old allocation when
-                        // writer.restore is Some, but we know that there
-                        // is a new allocation at this point, so the only
-                        // thing it can do is free memory
-                        writer.restore = Some(restore);
-
-                        writer.capacity = capacity;
-                        writer.base = base;
-                    }
+    /// Buffers an iterator to a slice on this stack, and splits it into
+    /// the items for which `pred` returns true and the rest. The items
+    /// which match keep their order, but the rest may not.
+    pub fn buffer_partition<T, P, F, R, I>(&self, i: I, mut pred: P, f: F) -> R
+    where
+        I: Iterator<Item = T>,
+        P: FnMut(&T) -> bool,
+        F: FnOnce(&mut [T], &mut [T]) -> R,
+    {
+        self.buffer(i, |items| {
+            // Only swaps, so the items are all still in the slice
+            // to be dropped if pred panics.
+            let mut split = 0;
+            for i in 0..items.len() {
+                if pred(&items[i]) {
+                    items.swap(split, i);
+                    split += 1;
                 }
-                writer.write(next);
             }
+            let (matching, rest) = items.split_at_mut(split);
+            f(matching, rest)
+        })
+    }
+
+    /// Buffers an iterator to a slice on this stack and gives temporary
+    /// access to the bytes of that slice, such as to hash them or write them
+    /// to a socket. `T` must be [`bytemuck::Pod`], so that every byte is
+    /// initialized and any bytes written are valid items.
+    #[cfg(feature = "bytemuck")]
+    pub fn buffer_bytes<T, F, R, I>(&self, i: I, f: F) -> R
+    where
+        T: bytemuck::Pod,
+        I: Iterator<Item = T>,
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        self.buffer(i, |items| {
+            // bytemuck does not cast from a ZST, which has no bytes anyway
+            if size_of::<T>() == 0 {
+                return f(&mut []);
+            }
+            f(bytemuck::cast_slice_mut(items))
+        })
+    }
+
+    /// Collects an iterator into a `Vec`, using this stack to hold the items
+    /// while the iterator runs. Only one heap allocation of exactly the right
+    /// size is made, which is helpful when the iterator gives a poor size hint.
+    pub fn buffer_to_vec<T, I>(&self, i: I) -> Vec<T>
+    where
+        I: Iterator<Item = T>,
+    {
+        let writer = self.buffer_writer(i);
+
+        // Allocate before taking the items from the writer, so that
+        // they are still dropped if this panics.
+        let mut vec = Vec::with_capacity(writer.len());
+        let (base, len, _restore) = writer.into_raw_parts();
+        unsafe {
+            ptr::copy_nonoverlapping(base, vec.as_mut_ptr(), len);
+            vec.set_len(len);
+        }
+        vec
+    }
+
+    /// Like [`buffer_to_vec`](Stack::buffer_to_vec), but returns a boxed
+    /// slice, which has no spare capacity.
+    pub fn buffer_to_boxed_slice<T, I>(&self, i: I) -> Box<[T]>
+    where
+        I: Iterator<Item = T>,
+    {
+        // The Vec is allocated with exactly the right capacity,
+        // so this does not reallocate.
+        self.buffer_to_vec(i).into_boxed_slice()
+    }
+
+    /// Buffers an iterator to a slice on this stack, gives `f` a chance to
+    /// process the slice in place, and then moves the items into an `Arc<[T]>`
+    /// to be shared. Only one heap allocation of exactly the right size is made.
+    #[cfg(target_has_atomic = "ptr")]
+    pub fn buffer_to_arc<T, F, I>(&self, i: I, f: F) -> Arc<[T]>
+    where
+        I: Iterator<Item = T>,
+        F: FnOnce(&mut [T]),
+    {
+        let mut writer = self.buffer_writer(i);
+        f(writer.as_mut_slice());
+
+        let mut arc = Arc::new_uninit_slice(writer.len());
+        let (base, len, _restore) = writer.into_raw_parts();
+        unsafe {
+            // The Arc was just created, so it is not shared
+            let items = Arc::get_mut(&mut arc).unwrap_unchecked();
+            ptr::copy_nonoverlapping(base, items.as_mut_ptr() as *mut T, len);
+            arc.assume_init()
+        }
+    }
+
+    /// Like [`buffer_to_arc`](Stack::buffer_to_arc), but for an `Rc<[T]>`.
+    pub fn buffer_to_rc<T, F, I>(&self, i: I, f: F) -> Rc<[T]>
+    where
+        I: Iterator<Item = T>,
+        F: FnOnce(&mut [T]),
+    {
+        let mut writer = self.buffer_writer(i);
+        f(writer.as_mut_slice());
+
+        let mut rc = Rc::new_uninit_slice(writer.len());
+        let (base, len, _restore) = writer.into_raw_parts();
+        unsafe {
+            // The Rc was just created, so it is not shared
+            let items = Rc::get_mut(&mut rc).unwrap_unchecked();
+            ptr::copy_nonoverlapping(base, items.as_mut_ptr() as *mut T, len);
+            rc.assume_init()
+        }
+    }
+
+    // Buffers all of the items, without giving back the extra space
+    fn buffer_writer<T, I>(&self, i: I) -> Writer<'_, T>
+    where
+        I: Iterator<Item = T>,
+    {
+        let (lower, _upper) = i.size_hint();
+        let mut writer = Writer::with_capacity(&self.0, lower);
+        for next in i {
+            writer.push(next);
+        }
+        writer
+    }
+
+    /// Buffers an iterator to this stack in chunks of up to `chunk_len` items,
+    /// passing each chunk to `f` in turn. The same memory is reused for every
+    /// chunk, so unlike [`buffer`](Stack::buffer) this may be used with an
+    /// iterator that is too long to fit in memory. The last chunk may be
+    /// shorter, and `f` is not called at all for an empty iterator.
+    ///
+    /// Panics if `chunk_len` is 0.
+    pub fn buffer_chunks<T, F, I>(&self, i: I, chunk_len: usize, mut f: F)
+    where
+        I: Iterator<Item = T>,
+        F: FnMut(&mut [T]),
+    {
+        assert!(chunk_len != 0, "second-stack: chunk_len must be non-zero");
+
+        let (lower, _upper) = i.size_hint();
+        let mut writer = Writer::with_capacity(&self.0, lower.min(chunk_len));
+        let mut i = i.fuse();
+        loop {
+            for next in i.by_ref().take(chunk_len) {
+                writer.push(next);
+            }
+            let len = writer.len();
+            if len == 0 {
+                return;
+            }
+            f(writer.as_mut_slice());
+            writer.clear();
+            if len < chunk_len {
+                return;
+            }
+        }
+    }
+
+    /// Like [`buffer_chunks`](Stack::buffer_chunks), but `f` may stop early by
+    /// returning [`ControlFlow::Break`], after which no more items are taken
+    /// from the iterator. The last batch may be shorter, and is delivered
+    /// before the end of the iterator is reported. Returns `Break` if `f`
+    /// stopped early, and `Continue` if the iterator was exhausted.
+    ///
+    /// Panics if `batch` is 0.
+    pub fn buffer_batched<T, F, I>(&self, i: I, batch: usize, mut f: F) -> ControlFlow<()>
+    where
+        I: Iterator<Item = T>,
+        F: FnMut(&mut [T]) -> ControlFlow<()>,
+    {
+        assert!(batch != 0, "second-stack: batch must be non-zero");
+
+        let (lower, _upper) = i.size_hint();
+        let mut writer = Writer::with_capacity(&self.0, lower.min(batch));
+        let mut i = i.fuse();
+        loop {
+            for next in i.by_ref().take(batch) {
+                writer.push(next);
+            }
+            let len = writer.len();
+            if len == 0 {
+                return ControlFlow::Continue(());
+            }
+            f(writer.as_mut_slice())?;
+            writer.clear();
+            if len < batch {
+                return ControlFlow::Continue(());
+            }
+        }
+    }
+
+    /// Opens a [`StackScope`] on this stack, from which many slices may be
+    /// allocated that all remain valid until `f` returns.
+    pub fn scope<'a, F, R>(&'a self, f: F) -> R
+    where
+        F: FnOnce(&StackScope<'a>) -> R,
+    {
+        let scope = StackScope::new(&self.0);
+        f(&scope)
+    }
+
+    /// Buffers an iterator to a slice on this stack, and calls `f` for each
+    /// window of `window` consecutive items in that slice, as with
+    /// `slice::windows`. `f` is not called if there are fewer items than that.
+    ///
+    /// Panics if `window` is 0.
+    pub fn buffer_windows<T, F, I>(&self, i: I, window: usize, mut f: F)
+    where
+        I: Iterator<Item = T>,
+        F: FnMut(&[T]),
+    {
+        assert!(window != 0, "second-stack: window must be non-zero");
+
+        self.buffer(i, |items| {
+            for next in items.windows(window) {
+                f(next);
+            }
+        })
+    }
+
+    /// Like [`buffer`](Stack::buffer), but returns an error rather than
+    /// buffering more than `max_len` items, so that it may be used with an
+    /// iterator from untrusted input. When the iterator is too long, the
+    /// items buffered so far are dropped, and `f` is not called.
+    pub fn try_buffer_bounded<T, F, R, I>(
+        &self,
+        i: I,
+        max_len: usize,
+        f: F,
+    ) -> Result<R, BufferTooLarge>
+    where
+        I: Iterator<Item = T>,
+        F: FnOnce(&mut [T]) -> R,
+    {
+        let (lower, _upper) = i.size_hint();
+        let mut writer = Writer::with_capacity(&self.0, lower.min(max_len));
+        let mut i = i.fuse();
+        for next in i.by_ref().take(max_len) {
+            writer.push(next);
+        }
+        if i.next().is_some() {
+            return Err(BufferTooLarge { limit: max_len });
+        }
+        writer.shrink_to_fit();
+
+        Ok(f(writer.as_mut_slice()))
+    }
+
+    /// Buffers an iterator of pairs to two slices on this stack, one for
+    /// each half of the pair, and gives temporary access to those slices.
+    /// This is useful for splitting rows into columns.
+    pub fn buffer_unzip<A, B, F, R, I>(&self, i: I, f: F) -> R
+    where
+        I: Iterator<Item = (A, B)>,
+        F: FnOnce(&mut [A], &mut [B]) -> R,
+    {
+        let (lower, _upper) = i.size_hint();
+        let mut writer = PairWriter::with_capacity(&self.0, lower);
+        for next in i {
+            writer.push(next);
+        }
+
+        let (a, b) = writer.as_mut_slices();
+        f(a, b)
+    }
+
+    /// Buffers an iterator of results to a slice on this stack and gives
+    /// temporary access to that slice. Stops at the first error, dropping
+    /// the items buffered so far and returning that error without calling `f`.
+    pub fn buffer_results<T, E, F, R, I>(&self, i: I, f: F) -> Result<R, E>
+    where
+        I: Iterator<Item = Result<T, E>>,
+        F: FnOnce(&mut [T]) -> R,
+    {
+        let (lower, _upper) = i.size_hint();
+        let mut writer = Writer::with_capacity(&self.0, lower);
+        for next in i {
+            writer.push(next?);
+        }
+        writer.shrink_to_fit();
+
+        Ok(f(writer.as_mut_slice()))
+    }
+
+    /// Concatenates the strings from an iterator into one string on this
+    /// stack and gives temporary access to that string.
+    pub fn buffer_str<'i, F, R, I>(&self, i: I, f: F) -> R
+    where
+        I: Iterator<Item = &'i str>,
+        F: FnOnce(&str) -> R,
+    {
+        let mut writer = Writer::<u8>::new(&self.0);
+        for s in i {
+            writer.extend_from_slice(s.as_bytes());
+        }
+        writer.shrink_to_fit();
+
+        // The bytes are a concatenation of valid UTF-8 strings,
+        // which is itself valid UTF-8.
+        f(unsafe { str::from_utf8_unchecked(writer.as_mut_slice()) })
+    }
+
+    /// Joins the strings from an iterator into one string on this stack,
+    /// with `sep` between each of them, and gives temporary access to that
+    /// string. No strings give an empty string.
+    pub fn buffer_join<S, F, R, I>(&self, i: I, sep: &str, f: F) -> R
+    where
+        S: AsRef<str>,
+        I: Iterator<Item = S>,
+        F: FnOnce(&str) -> R,
+    {
+        let mut writer = Writer::<u8>::new(&self.0);
+        for (index, s) in i.enumerate() {
+            if index != 0 {
+                writer.extend_from_slice(sep.as_bytes());
+            }
+            writer.extend_from_slice(s.as_ref().as_bytes());
+        }
+        writer.shrink_to_fit();
+
+        // The bytes are a concatenation of valid UTF-8 strings,
+        // which is itself valid UTF-8.
+        f(unsafe { str::from_utf8_unchecked(writer.as_mut_slice()) })
+    }
 
-            // TODO: (Performance?) Drop reserve of unused stack, if any. We have over-allocated.
-            // TODO: (Performance?) Consider using size_hint
+    /// Copies `s` onto this stack with a NUL terminator, and gives temporary
+    /// access to it as a [`CStr`]. This avoids allocating a `CString` on the
+    /// heap for each call into C.
+    ///
+    /// Panics if `s` contains a NUL byte.
+    pub fn cstr<F, R>(&self, s: &str, f: F) -> R
+    where
+        F: FnOnce(&CStr) -> R,
+    {
+        let mut writer = Writer::<u8>::with_capacity(&self.0, s.len() + 1);
+        writer.extend_from_slice(s.as_bytes());
+        writer.push(0);
 
-            let buffer = slice::from_raw_parts_mut(writer.base, writer.len);
-            f(buffer)
+        match CStr::from_bytes_with_nul(writer.as_mut_slice()) {
+            Ok(cstr) => f(cstr),
+            Err(_) => panic!("second-stack: cstr with an interior NUL byte"),
         }
     }
+
+    /// Gives `f` a growable region of bytes on this stack, for when the
+    /// length is not known ahead of time. The bytes are available from the
+    /// writer until `f` returns, at which point the memory is given back.
+    pub fn writer<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut StackWriter<'_>) -> R,
+    {
+        let mut writer = StackWriter::new(&self.0);
+        f(&mut writer)
+    }
+
+    /// Like [`writer`](Stack::writer), but for items of any type, with room
+    /// for at least `cap` items before the writer needs to grow. This is the
+    /// building block under [`buffer`](Stack::buffer), for when the items do
+    /// not come from an iterator. The items are dropped and the memory is
+    /// given back when `f` returns.
+    pub fn writer_with_capacity<T, F, R>(&self, cap: usize, f: F) -> R
+    where
+        F: FnOnce(&mut StackWriter<'_, T>) -> R,
+    {
+        let mut writer = StackWriter::with_capacity(&self.0, cap);
+        f(&mut writer)
+    }
+
+    /// Formats `args` into a string on this stack, such as for a log
+    /// message, and gives temporary access to that string. Use with
+    /// `format_args!`. For more control, `write!` may be used with the
+    /// writer from [`writer`](Stack::writer).
+    ///
+    /// Panics if a formatting trait implementation returns an error.
+    pub fn format<F, R>(&self, args: fmt::Arguments<'_>, f: F) -> R
+    where
+        F: FnOnce(&str) -> R,
+    {
+        let mut writer = StackWriter::new(&self.0);
+        fmt::Write::write_fmt(&mut writer, args)
+            .expect("a formatting trait implementation returned an error");
+
+        // Only strings have been written to the writer
+        f(unsafe { str::from_utf8_unchecked(writer.as_slice()) })
+    }
+
+    /// Gives `f` an empty [`StackVec`] on this stack which items can be
+    /// pushed to one at a time. The items are dropped and the memory is
+    /// given back when `f` returns.
+    pub fn build_vec<T, F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut StackVec<'_, T>) -> R,
+    {
+        let mut vec = StackVec::new(&self.0);
+        f(&mut vec)
+    }
+
+    /// Buffers an iterator to a slice on this stack and passes the raw
+    /// pointer and length of that slice to `f`, which is typically an
+    /// `extern "C"` function. The items are dropped and the memory is
+    /// freed after `f` returns.
+    ///
+    /// `f` must not retain the pointer past its return.
+    pub fn buffer_ffi<T, F, R, I>(&self, i: I, f: F) -> R
+    where
+        I: Iterator<Item = T>,
+        F: FnOnce(*mut T, usize) -> R,
+    {
+        self.buffer(i, |slice| f(slice.as_mut_ptr(), slice.len()))
+    }
+
+    /// Buffers two iterators to one slice on this stack, alternating
+    /// items from `a` and `b`. When one iterator runs out, the remainder
+    /// of the other is appended.
+    pub fn buffer_interleave<T, F, R, A, B>(&self, a: A, b: B, f: F) -> R
+    where
+        A: Iterator<Item = T>,
+        B: Iterator<Item = T>,
+        F: FnOnce(&mut [T]) -> R,
+    {
+        let interleave = Interleave {
+            a: a.fuse(),
+            b: b.fuse(),
+            take_b: false,
+        };
+        self.buffer(interleave, f)
+    }
 }
 
-/// Allocates an uninit slice from the threadlocal stack.
-pub fn uninit_slice<T, F, R>(len: usize, f: F) -> R
-where
-    F: FnOnce(&mut [MaybeUninit<T>]) -> R,
-{
-    THREAD_LOCAL.with(|stack| stack.uninit_slice(len, f))
+// Alternates between two iterators. Any panic from either
+// iterator happens inside of buffer, which cleans up the items
+// written so far.
+struct Interleave<A, B> {
+    a: Fuse<A>,
+    b: Fuse<B>,
+    take_b: bool,
 }
 
-/// Place a potentially very large value on the threadlocal second stack.
-pub fn uninit<T, F, R>(f: F) -> R
+impl<T, A, B> Iterator for Interleave<A, B>
 where
-    F: FnOnce(&mut MaybeUninit<T>) -> R,
+    A: Iterator<Item = T>,
+    B: Iterator<Item = T>,
 {
-    THREAD_LOCAL.with(|stack| stack.uninit(f))
+    type Item = T;
+    fn next(&mut self) -> Option<T> {
+        let take_b = self.take_b;
+        self.take_b = !take_b;
+        if take_b {
+            self.b.next().or_else(|| self.a.next())
+        } else {
+            self.a.next().or_else(|| self.b.next())
+        }
+    }
 }
 
-/// Buffers an iterator to a slice on the threadlocal stack and gives temporary access to that slice.
-/// Panics when running out of memory if the iterator is unbounded.
-pub fn buffer<T, F, R, I>(i: I, f: F) -> R
+/// Allocates an uninit slice from a caller-provided region of memory, like a
+/// memory-mapped file, instead of from a second stack. The slice is placed
+/// at the first offset in `backing` that is aligned for `T`.
+///
+/// Panics if `backing` is too small to hold the aligned slice.
+pub fn uninit_slice_in<T, F, R>(backing: &mut [MaybeUninit<u8>], len: usize, f: F) -> R
 where
-    I: Iterator<Item = T>,
-    F: FnOnce(&mut [T]) -> R,
+    F: FnOnce(&mut [MaybeUninit<T>]) -> R,
 {
-    THREAD_LOCAL.with(|stack| stack.buffer(i, f))
+    // A ZST slice of any len can live at a dangling pointer
+    if size_of::<T>() == 0 {
+        let ptr = ptr::NonNull::<MaybeUninit<T>>::dangling().as_ptr();
+        let slice = unsafe { slice::from_raw_parts_mut(ptr, len) };
+        return f(slice);
+    }
+
+    // Borrow the region as though it were an allocation, so that the
+    // same alignment logic as the stack applies. This is never
+    // freed because the caller owns the memory.
+    let mut region = Allocation {
+        base: backing.as_mut_ptr() as *mut u8,
+        capacity: backing.len(),
+        ..Allocation::null()
+    };
+
+    let required_bytes = size_of::<T>()
+        .checked_mul(len)
+        .and_then(|bytes| bytes.checked_add(region.padding::<T>()));
+    assert!(
+        matches!(required_bytes, Some(bytes) if bytes <= region.capacity),
+        "second-stack: backing region is too small"
+    );
+
+    let slice = unsafe {
+        let (ptr, _padding) = region.bump::<MaybeUninit<T>>(len);
+        slice::from_raw_parts_mut(ptr, len)
+    };
+
+    f(slice)
+}
+
+/// The error returned by [`Stack::try_buffer_bounded`] when the iterator
+/// has more items than the limit.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct BufferTooLarge {
+    /// The most items that were allowed
+    pub limit: usize,
+}
+
+impl fmt::Display for BufferTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "more than {} items to buffer", self.limit)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for BufferTooLarge {}
+
+/// The byte written over memory that is given back to a stack, when
+/// the `poison` feature is enabled, or `debug_assertions` are enabled
+/// without the `zeroize` feature.
+pub const POISON: u8 = 0xDD;
+
+/// Overwrites memory that is given back to a stack, as set by features.
+#[inline]
+#[allow(unused_variables)]
+pub(crate) unsafe fn scrub(ptr: *mut u8, len: usize) {
+    // Wipe the memory in a way that the optimizer
+    // can't skip, even though it is never read again.
+    #[cfg(feature = "zeroize")]
+    {
+        for i in 0..len {
+            ptr::write_volatile(ptr.add(i), 0);
+        }
+        core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
+    }
+    // Fill the memory with a recognizable pattern, so that
+    // a read of it through a stale pointer is easy to spot.
+    #[cfg(any(feature = "poison", all(debug_assertions, not(feature = "zeroize"))))]
+    ptr::write_bytes(ptr, POISON, len);
+}
+
+// A slice allocated on the heap in place of the stack, which is scrubbed
+// like the stack's own memory before it is freed, including on unwind.
+struct HeapSlice<T>(Vec<MaybeUninit<T>>);
+
+impl<T> Drop for HeapSlice<T> {
+    fn drop(&mut self) {
+        unsafe { scrub(self.0.as_mut_ptr() as *mut u8, size_of_val(&self.0[..])) }
+    }
 }
 
 // The logic to drop our Allocation goes into a drop impl so that if there
@@ -224,12 +1536,50 @@ pub(crate) struct DropStack<'a> {
     pub location: &'a UnsafeCell<Allocation>,
 }
 
+impl<'a> DropStack<'a> {
+    pub fn new(restore: Allocation, location: &'a UnsafeCell<Allocation>) -> Self {
+        #[cfg(feature = "stats")]
+        unsafe {
+            (*location.get()).live += 1;
+        }
+        Self { restore, location }
+    }
+
+    /// Gives up the restore, for when a restore made earlier covers
+    /// the same memory.
+    pub fn forget(self) {
+        #[cfg(feature = "stats")]
+        unsafe {
+            (*self.location.get()).live -= 1;
+        }
+        mem::forget(self);
+    }
+}
+
 impl Drop for DropStack<'_> {
     fn drop(&mut self) {
         unsafe {
-            let mut current = &mut *self.location.get();
+            let current = &mut *self.location.get();
+            #[cfg(feature = "stats")]
+            {
+                current.live -= 1;
+            }
             if current.ref_eq(&self.restore) {
+                // Slices are given back in the reverse order they were
+                // taken, so nothing below this one can be given back yet.
+                debug_assert!(
+                    self.restore.len <= current.len,
+                    "second-stack: non-LIFO restore detected"
+                );
+                scrub(
+                    current.base.add(self.restore.len),
+                    current.len - self.restore.len,
+                );
                 current.len = self.restore.len;
+                #[cfg(feature = "adaptive")]
+                if current.len == 0 {
+                    current.decay();
+                }
             } else {
                 self.restore.try_dealloc();
             }
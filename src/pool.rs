@@ -0,0 +1,65 @@
+//! A global pool of freed stack allocations, so that a new stack (such as
+//! the threadlocal stack of a new thread) can take one rather than going
+//! to the system allocator.
+
+use core::ptr;
+use std::sync::{Mutex, PoisonError};
+
+// Allocations are pooled for each power of two capacity from 64 bytes
+// up to 1MiB. Any others are freed as usual.
+const MIN_CLASS: u32 = 6;
+const MAX_CLASS: u32 = 20;
+const CLASSES: usize = (MAX_CLASS - MIN_CLASS + 1) as usize;
+// The most allocations kept of each capacity, which bounds
+// the pool to a total of less than 8MiB.
+const PER_CLASS: usize = 4;
+
+struct Pool {
+    allocations: [[*mut u8; PER_CLASS]; CLASSES],
+    lens: [usize; CLASSES],
+}
+
+// The allocations are not tied to any thread
+unsafe impl Send for Pool {}
+
+static POOL: Mutex<Pool> = Mutex::new(Pool {
+    allocations: [[ptr::null_mut(); PER_CLASS]; CLASSES],
+    lens: [0; CLASSES],
+});
+
+fn class(capacity: usize) -> Option<usize> {
+    if !capacity.is_power_of_two() {
+        return None;
+    }
+    let log = capacity.trailing_zeros();
+    if (MIN_CLASS..=MAX_CLASS).contains(&log) {
+        Some((log - MIN_CLASS) as usize)
+    } else {
+        None
+    }
+}
+
+/// Takes an allocation of exactly capacity bytes from the pool, if there is one.
+pub(crate) fn take(capacity: usize) -> Option<*mut u8> {
+    let class = class(capacity)?;
+    let mut pool = POOL.lock().unwrap_or_else(PoisonError::into_inner);
+    let len = pool.lens[class].checked_sub(1)?;
+    pool.lens[class] = len;
+    Some(pool.allocations[class][len])
+}
+
+/// Gives an allocation of capacity bytes to the pool. If the pool has no
+/// room for it, false is returned and the caller must free it instead.
+pub(crate) fn give(base: *mut u8, capacity: usize) -> bool {
+    let Some(class) = class(capacity) else {
+        return false;
+    };
+    let mut pool = POOL.lock().unwrap_or_else(PoisonError::into_inner);
+    let len = pool.lens[class];
+    if len == PER_CLASS {
+        return false;
+    }
+    pool.allocations[class][len] = base;
+    pool.lens[class] = len + 1;
+    true
+}
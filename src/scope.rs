@@ -0,0 +1,399 @@
+#[cfg(feature = "allocator_api")]
+use core::alloc::{AllocError, Allocator, Layout};
+use core::{
+    cell::{Cell, RefCell, UnsafeCell},
+    marker::PhantomData,
+    mem::{self, needs_drop, size_of, MaybeUninit},
+    ptr, slice,
+};
+
+use crate::{allocation::Allocation, DropStack, Writer};
+use alloc::vec::Vec;
+
+/// An arena on a [`Stack`](crate::Stack), created by [`Stack::scope`](crate::Stack::scope).
+///
+/// Every value and slice allocated from a scope stays valid until the scope ends,
+/// at which point destructors are run for all of them in the reverse order
+/// of allocation and the stack is restored with a single step. A `&StackScope`
+/// can be passed down a call tree so that each frame may allocate into it.
+///
+/// Allocating from a scope while a slice from some other use of the same
+/// stack is live above it (for example, from inside of a
+/// [`Stack::uninit_slice`](crate::Stack::uninit_slice) closure) panics,
+/// because the other use would reclaim the memory when it finished.
+pub struct StackScope<'a> {
+    location: &'a UnsafeCell<Allocation>,
+    // Restores for the first region in each allocation that the scope
+    // has used. The first is kept apart so that a scope which never
+    // leaves one allocation does not need to allocate a Vec.
+    first: Cell<Option<DropStack<'a>>>,
+    grown: RefCell<Vec<DropStack<'a>>>,
+    // The base of the allocation the scope last placed a region in.
+    last_base: Cell<*mut u8>,
+    // The top of the stack after the most recent allocation from this scope.
+    top: Cell<(*mut u8, usize)>,
+    // Linked list of slices that need to be dropped, latest first.
+    // The nodes themselves live on the stack.
+    drops: Cell<*mut DropNode>,
+    // Invariant so that values allocated from the scope must
+    // outlive the Stack borrow, and so outlive the scope.
+    _invariant: PhantomData<fn(&'a ()) -> &'a ()>,
+}
+
+struct DropNode {
+    next: *mut DropNode,
+    base: *mut u8,
+    len: usize,
+    drop: unsafe fn(*mut u8, usize),
+}
+
+unsafe fn drop_slice<T>(base: *mut u8, len: usize) {
+    ptr::drop_in_place(ptr::slice_from_raw_parts_mut(base as *mut T, len));
+}
+
+impl<'a> StackScope<'a> {
+    pub(crate) fn new(location: &'a UnsafeCell<Allocation>) -> Self {
+        let current = unsafe { &*location.get() };
+        Self {
+            location,
+            first: Cell::new(None),
+            grown: RefCell::new(Vec::new()),
+            last_base: Cell::new(ptr::null_mut()),
+            top: Cell::new((current.base, current.len)),
+            drops: Cell::new(ptr::null_mut()),
+            _invariant: PhantomData,
+        }
+    }
+
+    /// Buffers an iterator to a slice which is valid until the scope ends.
+    /// Do not use with an unbounded iterator, because this will eventually run out of memory and panic.
+    #[allow(clippy::mut_from_ref)]
+    pub fn buffer<T, I>(&self, i: I) -> &mut [T]
+    where
+        T: 'a,
+        I: Iterator<Item = T>,
+    {
+        self.check_top();
+
+        let mut writer = Writer::new(self.location);
+        for next in i {
+            writer.push(next);
+        }
+        writer.shrink_to_fit();
+
+        let (base, len, restore) = writer.into_raw_parts();
+        if let Some(restore) = restore {
+            self.keep(restore);
+        }
+
+        unsafe {
+            if len != 0 {
+                self.track_drop::<T>(base, len);
+            }
+            slice::from_raw_parts_mut(base, len)
+        }
+    }
+
+    /// Allocates an uninit slice which is valid until the scope ends, wrapped
+    /// in a guard that tracks how many elements have been initialized.
+    pub fn uninit_slice<T>(&self, len: usize) -> PartialInitGuard<'_, T>
+    where
+        T: 'a,
+    {
+        unsafe {
+            let base = self.alloc_raw::<MaybeUninit<T>>(len);
+            let node = if len != 0 {
+                self.track_drop::<T>(base as *mut T, 0)
+            } else {
+                ptr::null_mut()
+            };
+            PartialInitGuard {
+                slice: slice::from_raw_parts_mut(base, len),
+                init: 0,
+                node,
+            }
+        }
+    }
+
+    /// Allocates an uninit slice which is valid until the scope ends.
+    /// Nothing in the slice is ever dropped. See also
+    /// [`uninit_slice`](StackScope::uninit_slice), which tracks initialization.
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc_slice<T>(&self, len: usize) -> &mut [MaybeUninit<T>] {
+        unsafe { slice::from_raw_parts_mut(self.alloc_raw::<MaybeUninit<T>>(len), len) }
+    }
+
+    /// Moves `value` into the scope, returning a reference which is valid
+    /// until the scope ends. The value is dropped when the scope ends.
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc<T>(&self, value: T) -> &mut T
+    where
+        T: 'a,
+    {
+        let mut guard = self.uninit_slice(1);
+        guard.push(value);
+        &mut guard.into_init()[0]
+    }
+
+    /// Allocates a slice which is valid until the scope ends, initializing
+    /// each element with the result of `init(index)`. The elements are
+    /// dropped when the scope ends, or right away if `init` panics.
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc_slice_with<T, G>(&self, len: usize, mut init: G) -> &mut [T]
+    where
+        T: 'a,
+        G: FnMut(usize) -> T,
+    {
+        let mut guard = self.uninit_slice(len);
+        for index in 0..len {
+            guard.push(init(index));
+        }
+        guard.into_init()
+    }
+
+    // Panics if something other than this scope has allocated
+    // above the scope's most recent allocation.
+    fn check_top(&self) {
+        let stack = unsafe { &*self.location.get() };
+        let (base, len) = self.top.get();
+        // If the stack has since moved to a new allocation, nothing
+        // of ours can be in it yet.
+        let expected = if stack.base == base { len } else { 0 };
+        assert!(
+            stack.len == expected,
+            "second-stack: scope used while a nested allocation is live"
+        );
+    }
+
+    // Takes responsibility for a region allocated by this scope.
+    fn keep(&self, restore: DropStack<'a>) {
+        let stack = unsafe { &*self.location.get() };
+        self.top.set((stack.base, stack.len));
+
+        // Only the first region in each allocation needs restoring. Later
+        // regions in the same allocation are covered by it. The scope
+        // must not hold a restore for an allocation that it has not put
+        // anything into, since that allocation may be freed while idle.
+        if restore.restore.base != self.last_base.get() {
+            self.last_base.set(restore.restore.base);
+            match self.first.take() {
+                None => self.first.set(Some(restore)),
+                Some(first) => {
+                    self.first.set(Some(first));
+                    self.grown.borrow_mut().push(restore);
+                }
+            }
+        } else {
+            restore.forget();
+        }
+    }
+
+    // Allocates an uninit slice from the stack for the scope.
+    unsafe fn alloc_raw<T>(&self, len: usize) -> *mut T {
+        // See also: 2ec61cda-e074-4b26-a9a5-a01b70706585
+        // See also: 26936c11-5b7c-472e-8f63-7922e63a5425
+        if size_of::<T>() == 0 || len == 0 {
+            return ptr::NonNull::dangling().as_ptr();
+        }
+        self.check_top();
+        let stack = &mut *self.location.get();
+        let (restore, (base, _len)) = stack.get_slice(self.location, len);
+        self.keep(restore);
+        base
+    }
+
+    // Allocates size bytes aligned to align from the stack for the scope.
+    #[cfg(feature = "allocator_api")]
+    unsafe fn alloc_bytes(&self, size: usize, align: usize) -> *mut u8 {
+        self.check_top();
+        let stack = &mut *self.location.get();
+        let (restore, (base, _len), _padding) =
+            stack.get_slice_aligned::<u8>(self.location, size, align);
+        self.keep(restore);
+        base
+    }
+
+    // Whether the size bytes at ptr are the scope's most recent allocation,
+    // with nothing else above them.
+    #[cfg(feature = "allocator_api")]
+    fn is_top(&self, ptr: *mut u8, size: usize) -> bool {
+        let stack = unsafe { &*self.location.get() };
+        let (base, len) = self.top.get();
+        stack.base == base && stack.len == len && base.wrapping_add(len) == ptr.wrapping_add(size)
+    }
+
+    // Arranges for len items at base to be dropped when the scope ends,
+    // returning the node so that len can be updated later.
+    unsafe fn track_drop<T>(&self, base: *mut T, len: usize) -> *mut DropNode {
+        if !needs_drop::<T>() {
+            return ptr::null_mut();
+        }
+        let node = self.alloc_raw::<DropNode>(1);
+        node.write(DropNode {
+            next: self.drops.get(),
+            base: base as *mut u8,
+            len,
+            drop: drop_slice::<T>,
+        });
+        self.drops.set(node);
+        node
+    }
+}
+
+impl Drop for StackScope<'_> {
+    fn drop(&mut self) {
+        unsafe {
+            // Run destructors before any memory is freed. The nodes
+            // are read out first because they live alongside the data.
+            let mut next = self.drops.get();
+            while !next.is_null() {
+                let node = next.read();
+                next = node.next;
+                (node.drop)(node.base, node.len);
+            }
+        }
+
+        let grown = self.grown.get_mut();
+        while let Some(restore) = grown.pop() {
+            drop(restore);
+        }
+        drop(self.first.take());
+    }
+}
+
+/// A scope may be used as an allocator for the standard collections, such
+/// as with `Vec::new_in(&scope)`, with the `allocator_api` feature on
+/// nightly. As with any other allocation from the scope, the memory stays
+/// in use until the scope ends, except that freeing or growing the most
+/// recent allocation is done in place.
+#[cfg(feature = "allocator_api")]
+unsafe impl Allocator for &StackScope<'_> {
+    fn allocate(&self, layout: Layout) -> Result<ptr::NonNull<[u8]>, AllocError> {
+        let base = if layout.size() == 0 {
+            // Dangling, but aligned
+            ptr::without_provenance_mut(layout.align())
+        } else {
+            unsafe { self.alloc_bytes(layout.size(), layout.align()) }
+        };
+        let base = unsafe { ptr::NonNull::new_unchecked(base) };
+        Ok(ptr::NonNull::slice_from_raw_parts(base, layout.size()))
+    }
+
+    unsafe fn deallocate(&self, ptr: ptr::NonNull<u8>, layout: Layout) {
+        let ptr = ptr.as_ptr();
+        if layout.size() == 0 || !self.is_top(ptr, layout.size()) {
+            return;
+        }
+        let stack = &mut *self.location.get();
+        let len = ptr as usize - stack.base as usize;
+        if stack.give_back_to(len) {
+            self.top.set((stack.base, len));
+        }
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: ptr::NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<ptr::NonNull<[u8]>, AllocError> {
+        let old = ptr.as_ptr();
+        let stack = &mut *self.location.get();
+        if old_layout.size() != 0
+            && self.is_top(old, old_layout.size())
+            && old.align_offset(new_layout.align()) == 0
+        {
+            let start = old as usize - stack.base as usize;
+            if stack.capacity - start >= new_layout.size() {
+                stack.len = start + new_layout.size();
+                stack.update_peak();
+                self.top.set((stack.base, stack.len));
+                return Ok(ptr::NonNull::slice_from_raw_parts(ptr, new_layout.size()));
+            }
+        }
+
+        let new = self.allocate(new_layout)?;
+        ptr::copy_nonoverlapping(old, new.as_ptr() as *mut u8, old_layout.size());
+        self.deallocate(ptr, old_layout);
+        Ok(new)
+    }
+}
+
+/// An uninit slice from a [`StackScope`] which is initialized one element
+/// at a time. Only the initialized prefix is ever dropped.
+///
+/// Dropping the guard drops the initialized prefix immediately. Calling
+/// [`into_init`](PartialInitGuard::into_init) instead hands the prefix over
+/// to the scope, which drops it when the scope ends.
+pub struct PartialInitGuard<'s, T> {
+    slice: &'s mut [MaybeUninit<T>],
+    init: usize,
+    // Null when T does not need to be dropped
+    node: *mut DropNode,
+}
+
+impl<'s, T> PartialInitGuard<'s, T> {
+    /// Writes the next element. Panics if the slice is full.
+    pub fn push(&mut self, value: T) {
+        assert!(
+            self.init < self.slice.len(),
+            "second-stack: PartialInitGuard is full"
+        );
+        self.slice[self.init] = MaybeUninit::new(value);
+        self.init += 1;
+    }
+
+    /// The number of initialized elements
+    pub fn len(&self) -> usize {
+        self.init
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.init == 0
+    }
+
+    /// The total number of elements, initialized or not
+    pub fn capacity(&self) -> usize {
+        self.slice.len()
+    }
+
+    /// The initialized prefix
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        unsafe { &mut *(&mut self.slice[..self.init] as *mut [MaybeUninit<T>] as *mut [T]) }
+    }
+
+    /// The elements after the initialized prefix. Use with
+    /// [`assume_init`](PartialInitGuard::assume_init) to initialize
+    /// many elements at once.
+    pub fn spare_capacity_mut(&mut self) -> &mut [MaybeUninit<T>] {
+        &mut self.slice[self.init..]
+    }
+
+    /// Marks the next `count` elements as initialized.
+    ///
+    /// # Safety
+    /// The first `count` elements of
+    /// [`spare_capacity_mut`](PartialInitGuard::spare_capacity_mut) must be initialized.
+    pub unsafe fn assume_init(&mut self, count: usize) {
+        debug_assert!(count <= self.slice.len() - self.init);
+        self.init += count;
+    }
+
+    /// Returns the initialized prefix, which remains valid until the scope ends.
+    pub fn into_init(self) -> &'s mut [T] {
+        let this = mem::ManuallyDrop::new(self);
+        unsafe {
+            let node = this.node;
+            if !node.is_null() {
+                (*node).len = this.init;
+            }
+            slice::from_raw_parts_mut(this.slice.as_ptr() as *mut T, this.init)
+        }
+    }
+}
+
+impl<T> Drop for PartialInitGuard<'_, T> {
+    fn drop(&mut self) {
+        unsafe { ptr::drop_in_place(self.as_mut_slice()) }
+    }
+}
@@ -0,0 +1,694 @@
+use core::{cell::Cell, cmp::Ordering, ffi::CStr, mem::MaybeUninit, ops::ControlFlow, ptr};
+use std::{rc::Rc, sync::Arc};
+
+use crate::{AllocError, BufferTooLarge, Stack, StackScope, StackVec, StackWriter, Zeroable};
+
+struct Local {
+    stack: Stack,
+    // The stack set by with_thread_stack, if any
+    redirect: Cell<*const Stack>,
+}
+
+thread_local!(
+    static THREAD_LOCAL: Local = const {
+        Local {
+            stack: Stack::new(),
+            redirect: Cell::new(ptr::null()),
+        }
+    }
+);
+
+// The stack used by the free functions on this thread
+fn with_local<R>(f: impl FnOnce(&Stack) -> R) -> R {
+    THREAD_LOCAL.with(|local| {
+        let redirect = local.redirect.get();
+        if redirect.is_null() {
+            f(&local.stack)
+        } else {
+            // Safety: with_thread_stack holds a borrow of the stack
+            // for as long as it is set.
+            f(unsafe { &*redirect })
+        }
+    })
+}
+
+// Reservations from C may outlive a call to with_thread_stack, so always
+// use the thread's own stack.
+#[cfg(feature = "ffi")]
+pub(crate) fn with_stack<R>(f: impl FnOnce(&Stack) -> R) -> R {
+    THREAD_LOCAL.with(|local| f(&local.stack))
+}
+
+/// Makes the free functions in this crate use `stack` in place of the
+/// threadlocal stack until `f` returns, including when `f` panics. This is
+/// for testing code which uses the threadlocal stack, such as to check the
+/// capacity it needed. Calls may be nested, in which case the innermost
+/// stack is used.
+pub fn with_thread_stack<F, R>(stack: &Stack, f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    // Puts back the previous stack when dropped, so that it is
+    // also done when unwinding.
+    struct Restore(*const Stack);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            let previous = self.0;
+            THREAD_LOCAL.with(|local| local.redirect.set(previous));
+        }
+    }
+
+    let _restore = Restore(THREAD_LOCAL.with(|local| local.redirect.replace(stack)));
+    f()
+}
+
+/// Calls `f`, and returns its result along with the most bytes of the
+/// threadlocal stack that were in use at once during the call.
+/// See [`Stack::measure`].
+#[cfg(feature = "stats")]
+pub fn measure<F, R>(f: F) -> (R, usize)
+where
+    F: FnOnce() -> R,
+{
+    with_local(|stack| stack.measure(f))
+}
+
+/// Allocates an uninit slice from the threadlocal stack.
+pub fn uninit_slice<T, F, R>(len: usize, f: F) -> R
+where
+    F: FnOnce(&mut [MaybeUninit<T>]) -> R,
+{
+    with_local(|stack| stack.uninit_slice(len, f))
+}
+
+/// Allocates an uninit array of `N` items from the threadlocal stack.
+pub fn uninit_array<T, const N: usize, F, R>(f: F) -> R
+where
+    F: FnOnce(&mut [MaybeUninit<T>; N]) -> R,
+{
+    with_local(|stack| stack.uninit_array(f))
+}
+
+/// Allocates `len` bytes from the threadlocal stack, aligned to `align`, and
+/// passes `f` a raw pointer to them. See [`Stack::with_raw_bytes`].
+pub fn with_raw_bytes<F, R>(len: usize, align: usize, f: F) -> R
+where
+    F: FnOnce(*mut u8, usize) -> R,
+{
+    with_local(|stack| stack.with_raw_bytes(len, align, f))
+}
+
+/// Allocates an uninit slice of up to `len` items from the threadlocal stack,
+/// and gives back the part after the prefix that `init` fills. See
+/// [`Stack::uninit_slice_commit`].
+pub fn uninit_slice_commit<T, G, F, R>(len: usize, init: G, f: F) -> R
+where
+    G: FnOnce(&mut [MaybeUninit<T>]) -> usize,
+    F: FnOnce(&mut [MaybeUninit<T>]) -> R,
+{
+    with_local(|stack| stack.uninit_slice_commit(len, init, f))
+}
+
+/// Allocates two uninit slices from the threadlocal stack at once.
+pub fn uninit_slice2<A, B, F, R>(len_a: usize, len_b: usize, f: F) -> R
+where
+    F: FnOnce(&mut [MaybeUninit<A>], &mut [MaybeUninit<B>]) -> R,
+{
+    with_local(|stack| stack.uninit_slice2(len_a, len_b, f))
+}
+
+/// Like [`uninit_slice`], but returns an error instead of aborting the
+/// process if the threadlocal stack cannot grow.
+pub fn try_uninit_slice<T, F, R>(len: usize, f: F) -> Result<R, AllocError>
+where
+    F: FnOnce(&mut [MaybeUninit<T>]) -> R,
+{
+    with_local(|stack| stack.try_uninit_slice(len, f))
+}
+
+/// Allocates an uninit slice from the threadlocal stack, with its start
+/// aligned to at least `align` bytes. `align` must be a power of two.
+pub fn uninit_slice_aligned<T, F, R>(len: usize, align: usize, f: F) -> R
+where
+    F: FnOnce(&mut [MaybeUninit<T>]) -> R,
+{
+    with_local(|stack| stack.uninit_slice_aligned(len, align, f))
+}
+
+/// Allocates a slice from the threadlocal stack, initializing each element with
+/// the result of `init(index)`.
+pub fn uninit_slice_with<T, G, F, R>(len: usize, init: G, f: F) -> R
+where
+    G: FnMut(usize) -> T,
+    F: FnOnce(&mut [T]) -> R,
+{
+    with_local(|stack| stack.uninit_slice_with(len, init, f))
+}
+
+/// Allocates a slice from the threadlocal stack with all bytes set to zero.
+pub fn uninit_slice_zeroed<T, F, R>(len: usize, f: F) -> R
+where
+    T: Zeroable,
+    F: FnOnce(&mut [T]) -> R,
+{
+    with_local(|stack| stack.uninit_slice_zeroed(len, f))
+}
+
+/// Allocates an uninit slice from the threadlocal stack, and also passes to `f`
+/// the number of bytes of alignment padding inserted before the slice.
+pub fn uninit_slice_padding<T, F, R>(len: usize, f: F) -> R
+where
+    F: FnOnce(&mut [MaybeUninit<T>], usize) -> R,
+{
+    with_local(|stack| stack.uninit_slice_padding(len, f))
+}
+
+/// Place a potentially very large value on the threadlocal second stack.
+pub fn uninit<T, F, R>(f: F) -> R
+where
+    F: FnOnce(&mut MaybeUninit<T>) -> R,
+{
+    with_local(|stack| stack.uninit(f))
+}
+
+/// Moves `value` onto the threadlocal stack and calls `f` with it. The value
+/// is dropped after `f` returns, or if `f` panics.
+pub fn with<T, F, R>(value: T, f: F) -> R
+where
+    F: FnOnce(&mut T) -> R,
+{
+    with_local(|stack| stack.with(value, f))
+}
+
+/// Like [`uninit`], but returns an error instead of aborting the
+/// process if the threadlocal stack cannot grow.
+pub fn try_uninit<T, F, R>(f: F) -> Result<R, AllocError>
+where
+    F: FnOnce(&mut MaybeUninit<T>) -> R,
+{
+    with_local(|stack| stack.try_uninit(f))
+}
+
+/// Buffers an iterator to a slice on the threadlocal stack and gives temporary access to that slice.
+/// Panics when running out of memory if the iterator is unbounded.
+pub fn buffer<T, F, R, I>(i: I, f: F) -> R
+where
+    I: Iterator<Item = T>,
+    F: FnOnce(&mut [T]) -> R,
+{
+    with_local(|stack| stack.buffer(i, f))
+}
+
+/// Buffers the items from the start of an iterator for which `pred` returns
+/// true to the threadlocal stack. See [`Stack::buffer_take_while`].
+pub fn buffer_take_while<T, P, F, R, I>(i: I, pred: P, f: F) -> R
+where
+    P: FnMut(&T) -> bool,
+    I: Iterator<Item = T>,
+    F: FnOnce(&mut [T]) -> R,
+{
+    with_local(|stack| stack.buffer_take_while(i, pred, f))
+}
+
+/// Buffers the items of an iterator after skipping those at the start for
+/// which `pred` returns true to the threadlocal stack. See
+/// [`Stack::buffer_skip_while`].
+pub fn buffer_skip_while<T, P, F, R, I>(i: I, pred: P, f: F) -> R
+where
+    P: FnMut(&T) -> bool,
+    I: Iterator<Item = T>,
+    F: FnOnce(&mut [T]) -> R,
+{
+    with_local(|stack| stack.buffer_skip_while(i, pred, f))
+}
+
+/// Buffers the items of each of the inner iterators to one slice on the
+/// threadlocal stack, in order. See [`Stack::buffer_flatten`].
+pub fn buffer_flatten<T, F, R, I, J>(i: I, f: F) -> R
+where
+    I: Iterator<Item = J>,
+    J: IntoIterator<Item = T>,
+    F: FnOnce(&mut [T]) -> R,
+{
+    with_local(|stack| stack.buffer_flatten(i, f))
+}
+
+/// Buffers an iterator to a slice on the threadlocal stack while folding
+/// `step` over each item, and gives temporary access to the slice along with
+/// the final state. See [`Stack::buffer_scan`].
+pub fn buffer_scan<T, St, S, F, R, I>(i: I, init: St, step: S, f: F) -> R
+where
+    S: FnMut(&mut St, &T),
+    I: Iterator<Item = T>,
+    F: FnOnce(&mut [T], St) -> R,
+{
+    with_local(|stack| stack.buffer_scan(i, init, step, f))
+}
+
+/// Buffers an iterator to a slice on the threadlocal stack, pairing each item
+/// with its position in the iterator, and gives temporary access to that slice.
+pub fn buffer_enumerate<T, F, R, I>(i: I, f: F) -> R
+where
+    I: Iterator<Item = T>,
+    F: FnOnce(&mut [(usize, T)]) -> R,
+{
+    with_local(|stack| stack.buffer_enumerate(i, f))
+}
+
+/// Buffers an iterator to a slice on the threadlocal stack and gives temporary
+/// access to that slice along with the index of the item with the largest key.
+/// See [`Stack::buffer_max_by_key`].
+pub fn buffer_max_by_key<T, K, P, F, R, I>(i: I, key: P, f: F) -> R
+where
+    K: Ord,
+    P: FnMut(&T) -> K,
+    I: Iterator<Item = T>,
+    F: FnOnce(&mut [T], Option<usize>) -> R,
+{
+    with_local(|stack| stack.buffer_max_by_key(i, key, f))
+}
+
+/// Buffers an iterator to a slice on the threadlocal stack and gives temporary
+/// access to that slice along with the index of the item with the smallest key.
+/// See [`Stack::buffer_min_by_key`].
+pub fn buffer_min_by_key<T, K, P, F, R, I>(i: I, key: P, f: F) -> R
+where
+    K: Ord,
+    P: FnMut(&T) -> K,
+    I: Iterator<Item = T>,
+    F: FnOnce(&mut [T], Option<usize>) -> R,
+{
+    with_local(|stack| stack.buffer_min_by_key(i, key, f))
+}
+
+/// Like [`buffer`], but a panic in the iterator or in `f` is caught and
+/// returned as an error. The caller takes responsibility for unwind safety,
+/// as described for [`Stack::catch_buffer`].
+pub fn catch_buffer<T, F, R, I>(i: I, f: F) -> std::thread::Result<R>
+where
+    I: Iterator<Item = T>,
+    F: FnOnce(&mut [T]) -> R,
+{
+    with_local(|stack| stack.catch_buffer(i, f))
+}
+
+/// Like [`buffer`], but for `Copy` items from an iterator of known length,
+/// for which room is made up front. See [`Stack::buffer_copy`].
+pub fn buffer_copy<T, F, R, I>(i: I, f: F) -> R
+where
+    T: Copy,
+    I: ExactSizeIterator<Item = T>,
+    F: FnOnce(&mut [T]) -> R,
+{
+    with_local(|stack| stack.buffer_copy(i, f))
+}
+
+/// Copies `src` to a slice on the threadlocal stack and gives temporary
+/// access to that slice.
+pub fn copy_slice<T, F, R>(src: &[T], f: F) -> R
+where
+    T: Copy,
+    F: FnOnce(&mut [T]) -> R,
+{
+    with_local(|stack| stack.copy_slice(src, f))
+}
+
+/// Like [`buffer`], but makes room for at least `cap_hint` items up front.
+pub fn buffer_with_capacity<T, F, R, I>(i: I, cap_hint: usize, f: F) -> R
+where
+    I: Iterator<Item = T>,
+    F: FnOnce(&mut [T]) -> R,
+{
+    with_local(|stack| stack.buffer_with_capacity(i, cap_hint, f))
+}
+
+/// Buffers an iterator of results to a slice on the threadlocal stack and gives
+/// temporary access to that slice. Stops at the first error and returns it.
+pub fn buffer_results<T, E, F, R, I>(i: I, f: F) -> Result<R, E>
+where
+    I: Iterator<Item = Result<T, E>>,
+    F: FnOnce(&mut [T]) -> R,
+{
+    with_local(|stack| stack.buffer_results(i, f))
+}
+
+/// Buffers an iterator to a slice on the threadlocal stack, and sorts
+/// the slice before giving temporary access to it.
+pub fn buffer_sorted<T, F, R, I>(i: I, f: F) -> R
+where
+    T: Ord,
+    I: Iterator<Item = T>,
+    F: FnOnce(&mut [T]) -> R,
+{
+    with_local(|stack| stack.buffer_sorted(i, f))
+}
+
+/// Like [`buffer_sorted`], but sorts with the comparator `compare`.
+pub fn buffer_sorted_by<T, C, F, R, I>(i: I, compare: C, f: F) -> R
+where
+    I: Iterator<Item = T>,
+    C: FnMut(&T, &T) -> Ordering,
+    F: FnOnce(&mut [T]) -> R,
+{
+    with_local(|stack| stack.buffer_sorted_by(i, compare, f))
+}
+
+/// Buffers an iterator to a slice on the threadlocal stack, leaving out
+/// consecutive repeated items, and gives temporary access to that slice.
+pub fn buffer_dedup<T, F, R, I>(i: I, f: F) -> R
+where
+    T: PartialEq,
+    I: Iterator<Item = T>,
+    F: FnOnce(&mut [T]) -> R,
+{
+    with_local(|stack| stack.buffer_dedup(i, f))
+}
+
+/// Like [`buffer_dedup`], but compares the keys returned by `key`.
+pub fn buffer_dedup_by_key<T, K, G, F, R, I>(i: I, key: G, f: F) -> R
+where
+    K: PartialEq,
+    I: Iterator<Item = T>,
+    G: FnMut(&mut T) -> K,
+    F: FnOnce(&mut [T]) -> R,
+{
+    with_local(|stack| stack.buffer_dedup_by_key(i, key, f))
+}
+
+/// Buffers an iterator to the threadlocal stack, and splits it into the
+/// items for which `pred` returns true and the rest.
+pub fn buffer_partition<T, P, F, R, I>(i: I, pred: P, f: F) -> R
+where
+    I: Iterator<Item = T>,
+    P: FnMut(&T) -> bool,
+    F: FnOnce(&mut [T], &mut [T]) -> R,
+{
+    with_local(|stack| stack.buffer_partition(i, pred, f))
+}
+
+/// Buffers an iterator to a slice on the threadlocal stack and gives
+/// temporary access to the bytes of that slice.
+#[cfg(feature = "bytemuck")]
+pub fn buffer_bytes<T, F, R, I>(i: I, f: F) -> R
+where
+    T: bytemuck::Pod,
+    I: Iterator<Item = T>,
+    F: FnOnce(&mut [u8]) -> R,
+{
+    with_local(|stack| stack.buffer_bytes(i, f))
+}
+
+/// Collects an iterator into a `Vec`, using the threadlocal stack to hold
+/// the items while the iterator runs, so that only one heap allocation is made.
+pub fn buffer_to_vec<T, I>(i: I) -> Vec<T>
+where
+    I: Iterator<Item = T>,
+{
+    with_local(|stack| stack.buffer_to_vec(i))
+}
+
+/// Collects an iterator into a boxed slice, using the threadlocal stack to
+/// hold the items while the iterator runs, so that only one heap allocation is made.
+pub fn buffer_to_boxed_slice<T, I>(i: I) -> Box<[T]>
+where
+    I: Iterator<Item = T>,
+{
+    with_local(|stack| stack.buffer_to_boxed_slice(i))
+}
+
+/// Buffers an iterator to the threadlocal stack, gives `f` a chance to
+/// process the items in place, and then moves them into an `Arc<[T]>`.
+pub fn buffer_to_arc<T, F, I>(i: I, f: F) -> Arc<[T]>
+where
+    I: Iterator<Item = T>,
+    F: FnOnce(&mut [T]),
+{
+    with_local(|stack| stack.buffer_to_arc(i, f))
+}
+
+/// Like [`buffer_to_arc`], but for an `Rc<[T]>`.
+pub fn buffer_to_rc<T, F, I>(i: I, f: F) -> Rc<[T]>
+where
+    I: Iterator<Item = T>,
+    F: FnOnce(&mut [T]),
+{
+    with_local(|stack| stack.buffer_to_rc(i, f))
+}
+
+/// Buffers an iterator to the threadlocal stack in chunks of up to
+/// `chunk_len` items, passing each chunk to `f` in turn.
+pub fn buffer_chunks<T, F, I>(i: I, chunk_len: usize, f: F)
+where
+    I: Iterator<Item = T>,
+    F: FnMut(&mut [T]),
+{
+    with_local(|stack| stack.buffer_chunks(i, chunk_len, f))
+}
+
+/// Buffers an iterator to the threadlocal stack in batches of up to `batch`
+/// items, until `f` returns [`ControlFlow::Break`] or the iterator ends.
+pub fn buffer_batched<T, F, I>(i: I, batch: usize, f: F) -> ControlFlow<()>
+where
+    I: Iterator<Item = T>,
+    F: FnMut(&mut [T]) -> ControlFlow<()>,
+{
+    with_local(|stack| stack.buffer_batched(i, batch, f))
+}
+
+/// Buffers an iterator to the threadlocal stack, and calls `f` for each
+/// window of `window` consecutive items.
+pub fn buffer_windows<T, F, I>(i: I, window: usize, f: F)
+where
+    I: Iterator<Item = T>,
+    F: FnMut(&[T]),
+{
+    with_local(|stack| stack.buffer_windows(i, window, f))
+}
+
+/// Like [`buffer`], but returns an error rather than buffering more than
+/// `max_len` items.
+pub fn try_buffer_bounded<T, F, R, I>(i: I, max_len: usize, f: F) -> Result<R, BufferTooLarge>
+where
+    I: Iterator<Item = T>,
+    F: FnOnce(&mut [T]) -> R,
+{
+    with_local(|stack| stack.try_buffer_bounded(i, max_len, f))
+}
+
+/// Buffers an iterator of pairs to two slices on the threadlocal stack, one
+/// for each half of the pair, and gives temporary access to those slices.
+pub fn buffer_unzip<A, B, F, R, I>(i: I, f: F) -> R
+where
+    I: Iterator<Item = (A, B)>,
+    F: FnOnce(&mut [A], &mut [B]) -> R,
+{
+    with_local(|stack| stack.buffer_unzip(i, f))
+}
+
+/// Opens a [`StackScope`] on the threadlocal stack, from which many slices
+/// may be allocated that all remain valid until `f` returns.
+pub fn scope<'a, F, R>(f: F) -> R
+where
+    F: FnOnce(&StackScope<'a>) -> R,
+{
+    with_local(|stack| {
+        // Safety: The scope is dropped before this returns, so the stack
+        // is never used through this reference after the thread ends, or
+        // after with_thread_stack stops borrowing it. 'a
+        // outlives this call, and so the values in the scope (which outlive
+        // 'a) are alive when the scope drops them.
+        let stack: &'a Stack = unsafe { &*(stack as *const Stack) };
+        stack.scope(f)
+    })
+}
+
+/// Formats `args` into a string on the threadlocal stack and gives temporary
+/// access to that string.
+pub fn format<F, R>(args: core::fmt::Arguments<'_>, f: F) -> R
+where
+    F: FnOnce(&str) -> R,
+{
+    with_local(|stack| stack.format(args, f))
+}
+
+/// Concatenates the strings from an iterator into one string on the threadlocal
+/// stack and gives temporary access to that string.
+pub fn buffer_str<'i, F, R, I>(i: I, f: F) -> R
+where
+    I: Iterator<Item = &'i str>,
+    F: FnOnce(&str) -> R,
+{
+    with_local(|stack| stack.buffer_str(i, f))
+}
+
+/// Joins the strings from an iterator into one string on the threadlocal
+/// stack, with `sep` between each of them, and gives temporary access to
+/// that string.
+pub fn buffer_join<S, F, R, I>(i: I, sep: &str, f: F) -> R
+where
+    S: AsRef<str>,
+    I: Iterator<Item = S>,
+    F: FnOnce(&str) -> R,
+{
+    with_local(|stack| stack.buffer_join(i, sep, f))
+}
+
+/// Copies `s` onto the threadlocal stack with a NUL terminator, and gives
+/// temporary access to it as a [`CStr`].
+///
+/// Panics if `s` contains a NUL byte.
+pub fn cstr<F, R>(s: &str, f: F) -> R
+where
+    F: FnOnce(&CStr) -> R,
+{
+    with_local(|stack| stack.cstr(s, f))
+}
+
+/// Gives `f` a growable region of bytes on the threadlocal stack, for when
+/// the length is not known ahead of time.
+pub fn writer<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut StackWriter<'_>) -> R,
+{
+    with_local(|stack| stack.writer(f))
+}
+
+/// Gives `f` a growable region of items on the threadlocal stack, with room
+/// for at least `cap` items before it needs to grow.
+pub fn writer_with_capacity<T, F, R>(cap: usize, f: F) -> R
+where
+    F: FnOnce(&mut StackWriter<'_, T>) -> R,
+{
+    with_local(|stack| stack.writer_with_capacity(cap, f))
+}
+
+/// Gives `f` an empty [`StackVec`] on the threadlocal stack which items
+/// can be pushed to one at a time.
+pub fn build_vec<T, F, R>(f: F) -> R
+where
+    F: FnOnce(&mut StackVec<'_, T>) -> R,
+{
+    with_local(|stack| stack.build_vec(f))
+}
+
+/// Buffers an iterator to a slice on the threadlocal stack and passes the raw
+/// pointer and length of that slice to `f`.
+/// `f` must not retain the pointer past its return.
+pub fn buffer_ffi<T, F, R, I>(i: I, f: F) -> R
+where
+    I: Iterator<Item = T>,
+    F: FnOnce(*mut T, usize) -> R,
+{
+    with_local(|stack| stack.buffer_ffi(i, f))
+}
+
+/// Buffers two iterators to one slice on the threadlocal stack, alternating
+/// items from `a` and `b` and appending the remainder of the longer one.
+pub fn buffer_interleave<T, F, R, A, B>(a: A, b: B, f: F) -> R
+where
+    A: Iterator<Item = T>,
+    B: Iterator<Item = T>,
+    F: FnOnce(&mut [T]) -> R,
+{
+    with_local(|stack| stack.buffer_interleave(a, b, f))
+}
+
+/// Grows the threadlocal stack so that at least `bytes` are available without
+/// needing to grow again. This does nothing if a slice from the threadlocal stack
+/// is currently in use, or there is already enough room.
+pub fn reserve(bytes: usize) {
+    with_local(|stack| stack.reserve(bytes))
+}
+
+/// Allocates the threadlocal stack's memory up front, with room for at least
+/// `bytes`, so that later use on this thread does not pay for growing it.
+/// This is meant for the start of a worker thread, and is the same as
+/// [`reserve`] with nothing in use. Warming up again with fewer bytes
+/// does nothing.
+pub fn warm_up(bytes: usize) {
+    reserve(bytes)
+}
+
+/// Replaces the memory held by the threadlocal stack with a smaller allocation
+/// with room for at least `bytes`, if no slices from it are in use. See
+/// [`Stack::reset_to_capacity`].
+pub fn reset_to_capacity(bytes: usize) {
+    with_local(|stack| stack.reset_to_capacity(bytes))
+}
+
+/// Hands the memory held by the threadlocal stack back to the OS, keeping
+/// the allocation, if no slices from it are in use. See [`Stack::release_to_os`].
+#[cfg(all(unix, feature = "madvise"))]
+pub fn release_to_os() {
+    with_local(|stack| stack.release_to_os())
+}
+
+/// Touches each page of the memory held by the threadlocal stack, if no
+/// slices from it are in use. See [`Stack::prefault`].
+#[cfg(all(unix, feature = "prefault"))]
+pub fn prefault() {
+    with_local(|stack| stack.prefault())
+}
+
+/// The number of bytes of the threadlocal stack that are committed to slices
+/// which are currently in use, including padding.
+pub fn current_bytes_in_use() -> usize {
+    with_local(|stack| stack.bytes_in_use())
+}
+
+/// Whether any slices from the threadlocal stack are currently in use.
+/// See [`Stack::is_borrowed`].
+pub fn is_borrowed() -> bool {
+    with_local(|stack| stack.is_borrowed())
+}
+
+/// The number of bytes the threadlocal stack can hold before it needs to grow.
+pub fn current_capacity() -> usize {
+    with_local(|stack| stack.capacity())
+}
+
+/// The number of bytes that can be taken from the threadlocal stack
+/// before it needs to grow.
+pub fn current_remaining_bytes() -> usize {
+    with_local(|stack| stack.remaining_bytes())
+}
+
+/// Methods for buffering any iterator to the threadlocal stack, so that
+/// buffering can be chained onto the end of an iterator.
+///
+/// ```
+/// use second_stack::SecondStackIterExt;
+///
+/// let total = [1, 2, 3]
+///     .iter()
+///     .map(|x| x * 10)
+///     .buffer_on_stack(|slice| slice.iter().sum::<i32>());
+/// assert_eq!(total, 60);
+///
+/// let largest = [3, 1, 2]
+///     .into_iter()
+///     .buffer_on_stack_sorted(|slice| slice[slice.len() - 1]);
+/// assert_eq!(largest, 3);
+/// ```
+pub trait SecondStackIterExt: Iterator + Sized {
+    /// Buffers this iterator to a slice on the threadlocal stack.
+    /// See [`buffer`].
+    fn buffer_on_stack<F, R>(self, f: F) -> R
+    where
+        F: FnOnce(&mut [Self::Item]) -> R,
+    {
+        buffer(self, f)
+    }
+
+    /// Buffers this iterator to a sorted slice on the threadlocal stack.
+    /// See [`buffer_sorted`].
+    fn buffer_on_stack_sorted<F, R>(self, f: F) -> R
+    where
+        Self::Item: Ord,
+        F: FnOnce(&mut [Self::Item]) -> R,
+    {
+        buffer_sorted(self, f)
+    }
+}
+
+impl<I: Iterator> SecondStackIterExt for I {}
@@ -0,0 +1,29 @@
+//! Events for the tracing feature, emitted whenever a stack's backing
+//! allocation grows, shrinks or is freed.
+
+#[inline]
+pub(crate) fn capacity_changed(old_capacity: usize, new_capacity: usize) {
+    // The fields are only evaluated when a subscriber is interested
+    if new_capacity == 0 {
+        tracing::debug!(
+            old_capacity,
+            new_capacity,
+            thread = ?std::thread::current().id(),
+            "second-stack allocation freed"
+        );
+    } else if new_capacity > old_capacity {
+        tracing::debug!(
+            old_capacity,
+            new_capacity,
+            thread = ?std::thread::current().id(),
+            "second-stack allocation grew"
+        );
+    } else {
+        tracing::debug!(
+            old_capacity,
+            new_capacity,
+            thread = ?std::thread::current().id(),
+            "second-stack allocation shrank"
+        );
+    }
+}
@@ -0,0 +1,499 @@
+use core::{
+    cell::UnsafeCell,
+    fmt,
+    mem::{align_of, size_of, ManuallyDrop},
+    ptr, slice,
+};
+
+use crate::{
+    allocation::{checked_bytes, slice_bytes, Allocation},
+    DropStack,
+};
+
+// Data goes in a struct in case user code panics.
+// User code includes Iterator::next, FnOnce, and Drop::drop
+pub(crate) struct Writer<'a, T> {
+    location: &'a UnsafeCell<Allocation>,
+    restore: Option<DropStack<'a>>,
+    base: *mut T,
+    len: usize,
+    capacity: usize,
+    // The top of the stack after the writer last took space from it
+    top: (*mut u8, usize),
+}
+
+impl<'a, T> Writer<'a, T> {
+    pub fn new(location: &'a UnsafeCell<Allocation>) -> Self {
+        let current = unsafe { &*location.get() };
+        Self {
+            location,
+            top: (current.base, current.len),
+            restore: None,
+            // Dangling rather than null so that an empty iterator
+            // still produces a valid (empty) slice.
+            base: ptr::NonNull::dangling().as_ptr(),
+            // A ZST never needs to allocate, and so has room for
+            // as many items as can be counted.
+            capacity: if size_of::<T>() == 0 { usize::MAX } else { 0 },
+            len: 0,
+        }
+    }
+
+    /// Creates a writer with room for at least capacity items
+    /// before it needs to grow.
+    pub fn with_capacity(location: &'a UnsafeCell<Allocation>, capacity: usize) -> Self {
+        let mut writer = Self::new(location);
+        if capacity > writer.capacity {
+            unsafe {
+                let stack = &mut *location.get();
+                let (restore, (base, capacity)) = stack.get_slice(location, capacity);
+                writer.restore = Some(restore);
+                writer.base = base;
+                writer.capacity = capacity;
+                writer.top = (stack.base, stack.len);
+            }
+        }
+        writer
+    }
+
+    pub fn push(&mut self, item: T) {
+        unsafe {
+            if self.capacity == self.len {
+                self.grow(self.len + 1);
+            }
+            self.base.add(self.len).write(item);
+            self.len += 1;
+        }
+    }
+
+    /// Ensures there is room for at least additional more items
+    pub fn reserve(&mut self, additional: usize) {
+        let required = checked_bytes(self.len.checked_add(additional));
+        while self.capacity < required {
+            unsafe { self.grow(required) }
+        }
+    }
+
+    pub fn extend_from_slice(&mut self, items: &[T])
+    where
+        T: Copy,
+    {
+        self.reserve(items.len());
+        unsafe {
+            ptr::copy_nonoverlapping(items.as_ptr(), self.base.add(self.len), items.len());
+        }
+        self.len += items.len();
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Drops the written items, keeping the space for reuse
+    pub fn clear(&mut self) {
+        let len = self.len;
+        // Set first in case a destructor panics
+        self.len = 0;
+        unsafe {
+            ptr::drop_in_place(ptr::slice_from_raw_parts_mut(self.base, len));
+        }
+    }
+
+    pub fn as_slice(&self) -> &[T] {
+        unsafe { slice::from_raw_parts(self.base, self.len) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        unsafe { slice::from_raw_parts_mut(self.base, self.len) }
+    }
+
+    /// Gives back any reserved space beyond the written items to the stack,
+    /// so that it may be used by nested allocations.
+    pub fn shrink_to_fit(&mut self) {
+        if let Some(restore) = &self.restore {
+            let stack = unsafe { &mut *self.location.get() };
+            // Only possible when the items are at the top of the stack.
+            if restore.restore.ref_eq(stack) {
+                let end = self.base as usize + size_of::<T>() * self.len;
+                if stack.give_back_to(end - stack.base as usize) {
+                    self.capacity = self.len;
+                    self.top = (stack.base, stack.len);
+                }
+            }
+        }
+    }
+
+    /// Gives up ownership of the written items without dropping them,
+    /// returning the slice and the restore for the region it lives in,
+    /// if any.
+    pub fn into_raw_parts(self) -> (*mut T, usize, Option<DropStack<'a>>) {
+        let mut this = ManuallyDrop::new(self);
+        let restore = this.restore.take();
+        (this.base, this.len, restore)
+    }
+
+    // Grows the capacity. This may need to be called more than
+    // once to reach the required capacity.
+    #[cold]
+    unsafe fn grow(&mut self, required: usize) {
+        let stack = &mut *self.location.get();
+
+        // Growing would take space out from under anything allocated
+        // above the writer, or be given back early when that finishes.
+        // If the stack has since moved to a new allocation, nothing
+        // of ours can be in it yet.
+        let expected = if stack.base == self.top.0 {
+            self.top.1
+        } else {
+            0
+        };
+        assert!(
+            stack.len == expected,
+            "second-stack: writer grown while a nested allocation is live"
+        );
+
+        // First try to use the same stack, but if that fails
+        // copy over to the upsized stack
+        if self.try_reuse(stack) || self.try_realloc(stack, required) {
+            return;
+        }
+
+        // This will always be a different allocation, otherwise
+        // try_reuse would have succeeded
+        let capacity = (self.len * 2).max(required).max(1);
+        let (restore, (base, capacity)) = stack.get_slice(self.location, capacity);
+
+        // Check for 0 is to avoid copy from null ptr (miri violation)
+        if self.len != 0 {
+            ptr::copy_nonoverlapping(self.base, base, self.len);
+        }
+
+        // This attempts to restore the old allocation when
+        // self.restore is Some, but we know that there
+        // is a new allocation at this point, so the only
+        // thing it can do is free memory
+        self.restore = Some(restore);
+        self.top = (stack.base, stack.len);
+
+        self.capacity = capacity;
+        self.base = base;
+    }
+
+    fn try_reuse(&mut self, stack: &mut Allocation) -> bool {
+        if let Some(prev) = &self.restore {
+            if prev.restore.ref_eq(stack) {
+                // If we are already are using this stack, we know the
+                // end ptr is already aligned. To double in size,
+                // we would need as many bytes as there are currently
+                // and do not need to align
+                let required_bytes = size_of::<T>() * self.capacity;
+
+                if stack.remaining_bytes() >= required_bytes {
+                    stack.len += required_bytes;
+                    stack.update_peak();
+                    self.capacity *= 2;
+                    self.top = (stack.base, stack.len);
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    // When the writer is the only thing in the stack's allocation, the
+    // allocation can be grown with realloc, which avoids a copy if the
+    // allocator is able to extend it in place.
+    unsafe fn try_realloc(&mut self, stack: &mut Allocation, required: usize) -> bool {
+        // realloc may free the old memory without it being wiped
+        if cfg!(feature = "zeroize") {
+            return false;
+        }
+        let prev = match &mut self.restore {
+            Some(prev) if prev.restore.ref_eq(stack) && prev.restore.len == 0 => prev,
+            _ => return false,
+        };
+
+        let capacity = (self.len * 2).max(required);
+        // Room for the items, and for alignment because
+        // realloc only guarantees an alignment of 1
+        let required_bytes =
+            checked_bytes(slice_bytes::<T>(capacity).checked_add(align_of::<T>() - 1));
+        let padding = self.base as usize - stack.base as usize;
+        stack.realloc(stack.config.next_capacity(stack.capacity, required_bytes));
+
+        // Re-align the items if the allocation moved to an address
+        // with a different alignment. The items are copied as bytes
+        // because they are not aligned where they are now.
+        let new_padding = stack.base.align_offset(align_of::<T>());
+        let base = stack.base.add(new_padding) as *mut T;
+        if padding != new_padding {
+            ptr::copy(
+                stack.base.add(padding),
+                base as *mut u8,
+                slice_bytes::<T>(self.len),
+            );
+        }
+
+        stack.len = new_padding + slice_bytes::<T>(capacity);
+        stack.update_peak();
+        prev.restore = Allocation {
+            len: 0,
+            ..stack.clone()
+        };
+        self.top = (stack.base, stack.len);
+        self.base = base;
+        self.capacity = capacity;
+        true
+    }
+}
+
+impl<T> Drop for Writer<'_, T> {
+    fn drop(&mut self) {
+        unsafe {
+            for i in 0..self.len {
+                self.base.add(i).drop_in_place()
+            }
+        }
+    }
+}
+
+// Like Writer, but for two regions which always have the same capacity,
+// so that pairs can be split into them as they are pushed. Both regions are
+// taken together, so that there is never one above the other to get stuck
+// under when growing.
+pub(crate) struct PairWriter<'a, A, B> {
+    location: &'a UnsafeCell<Allocation>,
+    restore: Option<DropStack<'a>>,
+    a: *mut A,
+    b: *mut B,
+    len: usize,
+    capacity: usize,
+}
+
+impl<'a, A, B> PairWriter<'a, A, B> {
+    /// Creates a writer with room for at least capacity pairs
+    /// before it needs to grow.
+    pub fn with_capacity(location: &'a UnsafeCell<Allocation>, capacity: usize) -> Self {
+        let mut writer = Self {
+            location,
+            restore: None,
+            a: ptr::NonNull::dangling().as_ptr(),
+            b: ptr::NonNull::dangling().as_ptr(),
+            len: 0,
+            // As with Writer, ZSTs never need to allocate
+            capacity: if size_of::<A>() == 0 && size_of::<B>() == 0 {
+                usize::MAX
+            } else {
+                0
+            },
+        };
+        if capacity > writer.capacity {
+            unsafe { writer.grow(capacity) }
+        }
+        writer
+    }
+
+    pub fn push(&mut self, (a, b): (A, B)) {
+        unsafe {
+            if self.capacity == self.len {
+                self.grow(self.len + 1);
+            }
+            self.a.add(self.len).write(a);
+            self.b.add(self.len).write(b);
+            self.len += 1;
+        }
+    }
+
+    pub fn as_mut_slices(&mut self) -> (&mut [A], &mut [B]) {
+        unsafe {
+            (
+                slice::from_raw_parts_mut(self.a, self.len),
+                slice::from_raw_parts_mut(self.b, self.len),
+            )
+        }
+    }
+
+    #[cold]
+    unsafe fn grow(&mut self, required: usize) {
+        let stack = &mut *self.location.get();
+
+        // Move both regions to larger ones above them. The old regions are
+        // not given back until the writer is done, unless the stack moves
+        // to a new allocation, which frees the old one if nothing else is
+        // using it.
+        let capacity = (self.len * 2).max(required).max(1);
+        stack.reserve_pair::<A, B>(capacity, capacity);
+        let restore = stack.clone();
+        let a = if size_of::<A>() == 0 {
+            self.a
+        } else {
+            stack.bump::<A>(capacity).0
+        };
+        let b = if size_of::<B>() == 0 {
+            self.b
+        } else {
+            stack.bump::<B>(capacity).0
+        };
+
+        // Check for 0 is to avoid copy from null ptr (miri violation)
+        if self.len != 0 {
+            ptr::copy_nonoverlapping(self.a, a, self.len);
+            ptr::copy_nonoverlapping(self.b, b, self.len);
+        }
+
+        // Giving back to the restore for the same allocation
+        // would give back the new regions too.
+        let same = match &self.restore {
+            Some(prev) => prev.restore.ref_eq(&restore),
+            None => false,
+        };
+        if !same {
+            self.restore = Some(DropStack::new(restore, self.location));
+        }
+
+        self.a = a;
+        self.b = b;
+        self.capacity = capacity;
+    }
+}
+
+impl<A, B> Drop for PairWriter<'_, A, B> {
+    fn drop(&mut self) {
+        let len = self.len;
+        // Set first in case a destructor panics
+        self.len = 0;
+        unsafe {
+            ptr::drop_in_place(ptr::slice_from_raw_parts_mut(self.a, len));
+            ptr::drop_in_place(ptr::slice_from_raw_parts_mut(self.b, len));
+        }
+    }
+}
+
+/// A growable region on a [`Stack`](crate::Stack), created by
+/// [`Stack::writer`](crate::Stack::writer) for bytes or by
+/// [`Stack::writer_with_capacity`](crate::Stack::writer_with_capacity) for
+/// any item type. With the `std` feature, a writer of bytes implements
+/// [`std::io::Write`], so it may be used to serialize a message of unknown
+/// length without going to the heap. It also implements
+/// [`core::fmt::Write`], for use with `write!`.
+///
+/// The writer grows in the same way as [`Stack::buffer`](crate::Stack::buffer).
+/// Writing to it while a slice from some other use of the same stack is live
+/// above it panics if the writer needs to grow. The written items are
+/// dropped when the writer goes out of scope, including when unwinding.
+pub struct StackWriter<'a, T = u8> {
+    inner: Writer<'a, T>,
+}
+
+impl<'a, T> StackWriter<'a, T> {
+    pub(crate) fn new(location: &'a UnsafeCell<Allocation>) -> Self {
+        Self {
+            inner: Writer::new(location),
+        }
+    }
+
+    pub(crate) fn with_capacity(location: &'a UnsafeCell<Allocation>, capacity: usize) -> Self {
+        Self {
+            inner: Writer::with_capacity(location, capacity),
+        }
+    }
+
+    /// The items written so far
+    pub fn as_slice(&self) -> &[T] {
+        self.inner.as_slice()
+    }
+
+    /// The items written so far
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        self.inner.as_mut_slice()
+    }
+
+    /// The number of items written so far
+    pub fn len(&self) -> usize {
+        self.inner.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.len == 0
+    }
+
+    /// Appends an item to the end of the writer, moving the existing
+    /// items to a larger region if necessary.
+    pub fn push(&mut self, item: T) {
+        self.inner.push(item);
+    }
+
+    /// Appends items to the end of the writer.
+    pub fn extend_from_slice(&mut self, items: &[T])
+    where
+        T: Copy,
+    {
+        self.inner.extend_from_slice(items);
+    }
+}
+
+/// A growable collection on a [`Stack`](crate::Stack), created by
+/// [`Stack::build_vec`](crate::Stack::build_vec). This is useful when the
+/// items are not all available from one iterator, as [`Stack::buffer`](crate::Stack::buffer)
+/// requires. The items are dropped when the vec goes out of scope.
+///
+/// Pushing to it while a slice from some other use of the same stack is
+/// live above it panics if the vec needs to grow.
+pub struct StackVec<'a, T> {
+    inner: Writer<'a, T>,
+}
+
+impl<'a, T> StackVec<'a, T> {
+    pub(crate) fn new(location: &'a UnsafeCell<Allocation>) -> Self {
+        Self {
+            inner: Writer::new(location),
+        }
+    }
+
+    /// Appends an item to the end of the vec, moving the existing
+    /// items to a larger region if necessary.
+    pub fn push(&mut self, item: T) {
+        self.inner.push(item);
+    }
+
+    /// The number of items in the vec
+    pub fn len(&self) -> usize {
+        self.inner.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.len == 0
+    }
+
+    pub fn as_slice(&self) -> &[T] {
+        self.inner.as_slice()
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        self.inner.as_mut_slice()
+    }
+}
+
+impl fmt::Write for StackWriter<'_, u8> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.inner.extend_from_slice(s.as_bytes());
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::io::Write for StackWriter<'_, u8> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.inner.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        self.inner.extend_from_slice(buf);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
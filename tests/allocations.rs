@@ -0,0 +1,318 @@
+//! Tests which count calls to the global allocator. These live in their own
+//! test binary because they install a global allocator.
+//! The pool feature changes how many allocations are made.
+#![cfg(not(feature = "pool"))]
+
+use second_stack::*;
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+    mem::MaybeUninit,
+    thread,
+};
+
+struct Counting;
+
+thread_local!(
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    static REALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    static FAIL: Cell<bool> = const { Cell::new(false) };
+    // The size of the frees to check, and whether the last one
+    // that was checked had been zeroed
+    static WATCH: Cell<usize> = const { Cell::new(0) };
+    static ZEROED: Cell<Option<bool>> = const { Cell::new(None) }
+);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        // Counted per-thread so that tests running in parallel
+        // do not interfere with each other
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        if FAIL.with(Cell::get) {
+            return std::ptr::null_mut();
+        }
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if layout.size() == WATCH.with(Cell::get) {
+            let bytes = std::slice::from_raw_parts(ptr, layout.size());
+            ZEROED.with(|zeroed| zeroed.set(Some(bytes.iter().all(|&byte| byte == 0))));
+        }
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        REALLOCATIONS.with(|count| count.set(count.get() + 1));
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+/// Returns the result of f and the number of allocations made while running it
+fn count_allocations<R>(f: impl FnOnce() -> R) -> (R, usize) {
+    let before = ALLOCATIONS.with(Cell::get);
+    let result = f();
+    let after = ALLOCATIONS.with(Cell::get);
+    (result, after - before)
+}
+
+#[test]
+fn buffer_uses_size_hint() {
+    let items: Vec<u64> = (0..50_000).collect();
+    let stack = Stack::new();
+
+    let (sum, allocations) =
+        count_allocations(|| stack.buffer(items.into_iter(), |items| items.iter().sum::<u64>()));
+    assert_eq!(sum, (0..50_000).sum());
+    assert_eq!(allocations, 1);
+}
+
+#[test]
+fn with_capacity_preallocates() {
+    let (stack, allocations) = count_allocations(|| Stack::with_capacity(1 << 20));
+    assert_eq!(allocations, 1);
+
+    let ((), allocations) = count_allocations(|| {
+        stack.uninit_slice(1 << 16, |slice: &mut [std::mem::MaybeUninit<u64>]| {
+            stack.uninit_slice(100, |_: &mut [std::mem::MaybeUninit<u64>]| {});
+            assert_eq!(slice.len(), 1 << 16);
+        })
+    });
+    assert_eq!(allocations, 0);
+
+    let ((), allocations) = count_allocations(|| drop(Stack::with_capacity(0)));
+    assert_eq!(allocations, 0);
+}
+
+#[test]
+fn reserve_threadlocal() {
+    reserve(1 << 16);
+    let (sum, allocations) =
+        count_allocations(|| buffer(0..1000u64, |items| items.iter().sum::<u64>()));
+    assert_eq!(sum, (0..1000).sum());
+    assert_eq!(allocations, 0);
+
+    // Does nothing while a slice is in use
+    buffer(0..10u64, |_| {
+        let ((), allocations) = count_allocations(|| reserve(1 << 20));
+        assert_eq!(allocations, 0);
+    });
+}
+
+#[test]
+fn warm_up_threadlocal() {
+    thread::spawn(|| {
+        let ((), allocations) = count_allocations(|| warm_up(1 << 16));
+        assert_eq!(allocations, 1);
+        assert!(current_capacity() >= 1 << 16);
+
+        // Warming up again with less does nothing
+        let ((), allocations) = count_allocations(|| warm_up(1 << 10));
+        assert_eq!(allocations, 0);
+
+        let ((), allocations) =
+            count_allocations(|| uninit_slice(1 << 13, |_: &mut [MaybeUninit<u64>]| {}));
+        assert_eq!(allocations, 0);
+
+        let ((), allocations) = count_allocations(|| warm_up(1 << 20));
+        assert_eq!(allocations, 1);
+    })
+    .join()
+    .unwrap();
+}
+
+#[test]
+#[cfg(not(feature = "zeroize"))] // realloc is not used, so nothing is left unwiped
+fn buffer_grows_with_realloc() {
+    let stack = Stack::new();
+    let items = (0..50_000u64).filter(|i| i % 2 == 0);
+
+    let reallocations = REALLOCATIONS.with(Cell::get);
+    let (sum, allocations) =
+        count_allocations(|| stack.buffer(items, |items| items.iter().sum::<u64>()));
+    assert_eq!(sum, (0..50_000).filter(|i| i % 2 == 0).sum());
+    // Only the first allocation is new, and the rest grow it
+    assert_eq!(allocations, 1);
+    assert!(REALLOCATIONS.with(Cell::get) > reallocations);
+}
+
+#[test]
+fn try_uninit_slice_reports_failure() {
+    let stack = Stack::with_capacity(64);
+    stack.uninit_slice(8, |_: &mut [std::mem::MaybeUninit<u8>]| {
+        FAIL.with(|fail| fail.set(true));
+        // Fits without allocating
+        let fits = stack.try_uninit_slice(8, |_: &mut [std::mem::MaybeUninit<u8>]| ());
+        let grows = stack.try_uninit_slice(1000, |_: &mut [std::mem::MaybeUninit<u8>]| ());
+        FAIL.with(|fail| fail.set(false));
+
+        assert_eq!(fits, Ok(()));
+        assert_eq!(grows, Err(AllocError));
+        assert_eq!(stack.bytes_in_use(), 8);
+        assert_eq!(stack.capacity(), 64);
+    });
+    assert_eq!(
+        stack.try_uninit_slice(1000, |slice: &mut [std::mem::MaybeUninit<u8>]| slice.len()),
+        Ok(1000)
+    );
+}
+
+#[test]
+fn zst_does_not_allocate() {
+    let stack = Stack::new();
+    let ((), allocations) = count_allocations(|| {
+        stack.buffer((0..1000).map(|_| ()), |items| assert_eq!(items.len(), 1000));
+        stack.uninit_slice(1000, |items: &mut [std::mem::MaybeUninit<()>]| {
+            assert_eq!(items.len(), 1000)
+        });
+    });
+    assert_eq!(allocations, 0);
+}
+
+#[test]
+fn buffer_to_vec_allocates_once() {
+    let stack = Stack::with_capacity(1 << 20);
+    let items = (0..10_000u64).filter(|i| i % 2 == 0);
+    let (vec, allocations) = count_allocations(|| stack.buffer_to_vec(items));
+    assert_eq!(allocations, 1);
+    assert_eq!(vec.len(), vec.capacity());
+    assert!(vec.into_iter().eq((0..10_000).filter(|i| i % 2 == 0)));
+    assert_eq!(stack.bytes_in_use(), 0);
+
+    assert_eq!(buffer_to_vec("abc".chars()), ['a', 'b', 'c']);
+}
+
+#[test]
+fn buffer_to_boxed_slice_allocates_once() {
+    let stack = Stack::with_capacity(1 << 20);
+    let items = (0..10_000u64).filter(|i| i % 2 == 0).map(Box::new);
+
+    let reallocations = REALLOCATIONS.with(Cell::get);
+    let (boxed, allocations) = count_allocations(|| stack.buffer_to_boxed_slice(items));
+    // One for each item, and one for the slice
+    assert_eq!(allocations, 5001);
+    assert_eq!(REALLOCATIONS.with(Cell::get), reallocations);
+    assert!(boxed
+        .iter()
+        .map(|item| **item)
+        .eq((0..10_000).filter(|i| i % 2 == 0)));
+    assert_eq!(stack.bytes_in_use(), 0);
+
+    assert_eq!(*buffer_to_boxed_slice("abc".chars()), ['a', 'b', 'c']);
+}
+
+#[test]
+fn aligned_slice_fills_allocation_exactly() {
+    let stack = Stack::with_capacity(64);
+    assert_eq!(stack.capacity(), 64);
+
+    // Only the padding which is actually needed is paid for, so slices
+    // which exactly fill the allocation do not make it grow.
+    let ((), allocations) = count_allocations(|| {
+        stack.uninit_slice(8, |_: &mut [MaybeUninit<u64>]| {});
+        stack.uninit_slice(8, |_: &mut [MaybeUninit<u8>]| {
+            stack.uninit_slice(7, |_: &mut [MaybeUninit<u64>]| {
+                assert_eq!(stack.remaining_bytes(), 0);
+            });
+        });
+    });
+    assert_eq!(allocations, 0);
+    assert_eq!(stack.capacity(), 64);
+}
+
+#[test]
+fn buffer_with_capacity_allocates_once() {
+    let stack = Stack::new();
+    let items = (0..20_000u64).filter(|i| i % 2 == 0);
+
+    let reallocations = REALLOCATIONS.with(Cell::get);
+    let (len, allocations) =
+        count_allocations(|| stack.buffer_with_capacity(items, 10_000, |items| items.len()));
+    assert_eq!(len, 10_000);
+    assert_eq!(allocations, 1);
+    assert_eq!(REALLOCATIONS.with(Cell::get), reallocations);
+
+    // More items than the hint still works
+    let len = buffer_with_capacity(0..100, 10, |items| items.len());
+    assert_eq!(len, 100);
+}
+
+#[test]
+fn try_uninit_reports_failure() {
+    let stack = Stack::new();
+    FAIL.with(|fail| fail.set(true));
+    let result = stack.try_uninit(|_: &mut std::mem::MaybeUninit<[u8; 4096]>| unreachable!());
+    FAIL.with(|fail| fail.set(false));
+    assert_eq!(result, Err::<(), _>(AllocError));
+    assert_eq!(stack.capacity(), 0);
+
+    let value = try_uninit(|value: &mut std::mem::MaybeUninit<[u8; 4096]>| {
+        value
+            .write([1; 4096])
+            .iter()
+            .map(|&byte| byte as usize)
+            .sum::<usize>()
+    });
+    assert_eq!(value, Ok(4096));
+}
+
+#[test]
+#[cfg(all(feature = "zeroize", not(feature = "poison")))]
+fn heap_slice_is_zeroed_before_free() {
+    let stack = Stack::builder().heap_threshold(1024).build();
+    WATCH.with(|watch| watch.set(4096));
+
+    stack.uninit_slice(4096, |slice: &mut [std::mem::MaybeUninit<u8>]| {
+        for byte in slice.iter_mut() {
+            byte.write(0xA5);
+        }
+    });
+    assert_eq!(ZEROED.with(Cell::take), Some(true));
+
+    let result = stack.try_uninit_slice(4096, |slice: &mut [std::mem::MaybeUninit<u8>]| {
+        for byte in slice.iter_mut() {
+            byte.write(0xA5);
+        }
+    });
+    assert_eq!(result, Ok(()));
+    assert_eq!(ZEROED.with(Cell::take), Some(true));
+
+    // Also when unwinding
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        stack.uninit_slice(4096, |slice: &mut [std::mem::MaybeUninit<u8>]| {
+            for byte in slice.iter_mut() {
+                byte.write(0xA5);
+            }
+            panic!("f failed");
+        })
+    }));
+    assert!(result.is_err());
+    WATCH.with(|watch| watch.set(0));
+    assert_eq!(ZEROED.with(Cell::take), Some(true));
+}
+
+#[test]
+#[cfg(all(feature = "adaptive", feature = "zeroize", not(feature = "poison")))]
+fn decay_zeroes_memory_before_free() {
+    let stack = Stack::builder().decay_window(4).build();
+    stack.uninit_slice(4096, |slice: &mut [std::mem::MaybeUninit<u8>]| {
+        for byte in slice.iter_mut() {
+            byte.write(0xA5);
+        }
+    });
+    assert_eq!(stack.capacity(), 4096);
+
+    WATCH.with(|watch| watch.set(4096));
+    let reallocations = REALLOCATIONS.with(Cell::get);
+    for _ in 0..4 {
+        stack.uninit_slice(10, |_: &mut [std::mem::MaybeUninit<u8>]| {});
+    }
+    WATCH.with(|watch| watch.set(0));
+    assert_eq!(stack.capacity(), 2048);
+    // realloc could free the old memory without it being wiped
+    assert_eq!(REALLOCATIONS.with(Cell::get), reallocations);
+    assert_eq!(ZEROED.with(Cell::take), Some(true));
+}
@@ -0,0 +1,72 @@
+//! Tests for the allocator_api feature, which requires nightly.
+#![cfg(feature = "allocator_api")]
+#![feature(allocator_api)]
+
+use second_stack::*;
+use std::collections::VecDeque;
+use testdrop::TestDrop;
+
+#[test]
+fn vec_in_scope() {
+    let stack = Stack::new();
+    stack.scope(|scope| {
+        let mut items = Vec::new_in(scope);
+        for i in 0..1000u32 {
+            items.push(i);
+        }
+        assert_eq!(items.iter().sum::<u32>(), 499500);
+        // Growing the most recent allocation is done in place
+        assert!(stack.bytes_in_use() < 8192);
+
+        let mut other = Vec::with_capacity_in(10, scope);
+        other.extend_from_slice(&items[..10]);
+        // The first vec is no longer at the top, so moves when it grows
+        items.extend(0..1000);
+        assert_eq!(items.len(), 2000);
+        assert_eq!(other, [0, 1, 2, 3, 4, 5, 6, 7, 8, 9]);
+    });
+    assert_eq!(stack.bytes_in_use(), 0);
+}
+
+#[test]
+fn collections_drop_items() {
+    let td = TestDrop::new();
+    scope(|scope| {
+        let mut queue = VecDeque::new_in(scope);
+        for _ in 0..100 {
+            queue.push_back(td.new_item().1);
+        }
+        queue.drain(..50);
+        assert_eq!(td.num_dropped_items(), 50);
+
+        let boxed = Box::new_in(td.new_item().1, scope);
+        drop(boxed);
+        assert_eq!(td.num_dropped_items(), 51);
+    });
+    assert_eq!(td.num_tracked_items(), 101);
+    assert_eq!(td.num_dropped_items(), 101);
+}
+
+#[test]
+fn freeing_the_latest_allocation_gives_it_back() {
+    let stack = Stack::with_capacity(1024);
+    stack.scope(|scope| {
+        let first = Box::new_in(1u64, scope);
+        let in_use = stack.bytes_in_use();
+        drop(Box::new_in([0u64; 16], scope));
+        assert_eq!(stack.bytes_in_use(), in_use);
+        assert_eq!(*first, 1);
+    });
+}
+
+#[test]
+#[should_panic(expected = "scope used while a nested allocation is live")]
+fn nested_allocation_panics() {
+    let stack = Stack::new();
+    stack.scope(|scope| {
+        let mut items = Vec::new_in(scope);
+        stack.uninit_slice(10, |_: &mut [std::mem::MaybeUninit<u8>]| {
+            items.push(1u8);
+        });
+    });
+}
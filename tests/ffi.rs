@@ -0,0 +1,65 @@
+//! Tests for the ffi feature, calling the C entry points from Rust.
+#![cfg(feature = "ffi")]
+
+use second_stack::*;
+use std::{mem::MaybeUninit, thread};
+
+#[test]
+fn reserve_and_release() {
+    thread::spawn(|| {
+        let a = second_stack_reserve(10, 1);
+        let b = second_stack_reserve(100, 64);
+        assert_eq!(b as usize % 64, 0);
+        unsafe { std::ptr::write_bytes(b, 1, 100) };
+        assert!(current_bytes_in_use() >= 100);
+
+        // Rust code called back from the host shares the stack
+        uninit_slice(10_000, |slice: &mut [MaybeUninit<u64>]| {
+            assert_eq!(slice.len(), 10_000);
+        });
+        assert_eq!(unsafe { *b.add(99) }, 1);
+
+        unsafe {
+            second_stack_release(b);
+            second_stack_release(a);
+        }
+        assert_eq!(current_bytes_in_use(), 0);
+
+        let empty = second_stack_reserve(0, 8);
+        assert_eq!(empty as usize % 8, 0);
+        unsafe { second_stack_release(empty) };
+
+        assert!(second_stack_reserve(8, 3).is_null());
+    })
+    .join()
+    .unwrap();
+}
+
+#[test]
+fn reserve_grows_stack() {
+    thread::spawn(|| {
+        let small = second_stack_reserve(16, 8);
+        // Moves to a new allocation while the first is in use
+        let large = second_stack_reserve(1 << 20, 8);
+        unsafe {
+            std::ptr::write_bytes(large, 0, 1 << 20);
+            second_stack_release(large);
+            second_stack_release(small);
+        }
+        assert_eq!(current_bytes_in_use(), 0);
+    })
+    .join()
+    .unwrap();
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "non-LIFO restore detected")]
+fn release_under_live_slice() {
+    let reserved = second_stack_reserve(16, 8);
+    uninit_slice(8, |_: &mut [MaybeUninit<u8>]| {
+        // Breaks the safety contract, by giving back memory
+        // from under the slice
+        unsafe { second_stack_release(reserved) };
+    });
+}
@@ -0,0 +1,32 @@
+//! Tests for the instrument feature, which sets a global hook.
+#![cfg(feature = "instrument")]
+
+use second_stack::*;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static GROWN: AtomicUsize = AtomicUsize::new(0);
+static FREED: AtomicUsize = AtomicUsize::new(0);
+
+fn count(event: GrowthEvent) {
+    if event.new_capacity > event.old_capacity {
+        GROWN.fetch_add(1, Ordering::Relaxed);
+    } else if event.new_capacity == 0 {
+        FREED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[test]
+fn growth_hook_is_called() {
+    set_growth_hook(Some(count));
+
+    let stack = Stack::new();
+    let grown = GROWN.load(Ordering::Relaxed);
+    stack.uninit_slice(1 << 16, |_: &mut [std::mem::MaybeUninit<u8>]| {});
+    assert!(GROWN.load(Ordering::Relaxed) > grown);
+
+    let freed = FREED.load(Ordering::Relaxed);
+    drop(stack);
+    assert!(FREED.load(Ordering::Relaxed) > freed);
+
+    set_growth_hook(None);
+}
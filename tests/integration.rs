@@ -4,7 +4,7 @@ use rand::{
     thread_rng, Rng, SeedableRng,
 };
 use second_stack::*;
-use std::{fmt::Debug, marker::PhantomData, mem::MaybeUninit, thread};
+use std::{fmt::Debug, marker::PhantomData, mem::MaybeUninit, ops::ControlFlow, thread};
 use testdrop::TestDrop;
 
 /// Randomly tests both uninit_slice and buffer
@@ -133,16 +133,16 @@ where
         let (mut rng_gen, mut rng_check) = rng_pair();
 
         assert_eq!(len, uninit.len());
-        for i in 0..uninit.len() {
+        for item in uninit.iter_mut() {
             let value = rng_gen.gen();
-            uninit[i] = MaybeUninit::new(value);
+            *item = MaybeUninit::new(value);
         }
         recurse(limit, local);
         let init = unsafe { &*(uninit as *const [MaybeUninit<T>] as *const [T]) };
         // Verify that nothing overwrote this array.
-        for i in 0..init.len() {
+        for item in init {
             let value = rng_check.gen();
-            assert_eq!(init[i], value);
+            assert_eq!(item, &value);
         }
     };
 
@@ -171,8 +171,45 @@ where
     }
 }
 
+/// Types with a greater alignment than any primitive, as for SIMD.
+/// These are only used with slices because a Huge value of them
+/// would be far too large.
+fn check_rand_aligned(limit: u32, local: &Stack) {
+    let switch = thread_rng().gen_range(0u32..6);
+    match switch {
+        0 => check_slice::<Align16<u8>>(limit, local),
+        1 => check_slice::<Align32<u16>>(limit, local),
+        2 => check_slice::<Align64<u32>>(limit, local),
+        3 => check_iter::<Align16<u8>>(limit, local),
+        4 => check_iter::<Align32<u16>>(limit, local),
+        5 => check_iter::<Align64<u32>>(limit, local),
+        _ => unreachable!(),
+    }
+}
+
+macro_rules! aligned {
+    ($($name:ident($align:literal)),*) => {
+        $(
+            #[derive(Copy, Clone, Debug, PartialEq)]
+            #[repr(align($align))]
+            struct $name<T>(T);
+
+            impl<T> Distribution<$name<T>> for Standard
+            where
+                Standard: Distribution<T>,
+            {
+                fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> $name<T> {
+                    $name(rng.gen())
+                }
+            }
+        )*
+    };
+}
+
+aligned!(Align16(16), Align32(32), Align64(64));
+
 fn check_rand_type(limit: u32, local: &Stack) {
-    let switch = thread_rng().gen_range(0u32..13);
+    let switch = thread_rng().gen_range(0u32..14);
     // Pick some types with varying size/alignment requirements
     match switch {
         0 => check_rand_method::<u8>(limit, local),
@@ -188,6 +225,7 @@ fn check_rand_type(limit: u32, local: &Stack) {
         10 => check_rand_method::<(u32, u16)>(limit, local),
         11 => check_rand_method::<(u32, u32)>(limit, local),
         12 => check_rand_method::<()>(limit, local),
+        13 => check_rand_aligned(limit, local),
         _ => unreachable!(),
     }
 }
@@ -297,13 +335,13 @@ where
         let value = self.rand.gen();
         let item = self.drop.new_item().1;
 
-        return Some(DropCheck {
+        Some(DropCheck {
             value,
             _item: item,
             probability,
             local: self.local,
             limit: self.limit,
-        });
+        })
     }
 }
 
@@ -322,6 +360,1932 @@ impl CallCheck {
 impl Drop for CallCheck {
     #[track_caller]
     fn drop(&mut self) {
-        assert!(self.called == true);
+        assert!(self.called);
+    }
+}
+
+#[test]
+fn const_stack_allocates_on_first_use() {
+    // Constructing in a const context yields an un-warmed stack
+    let stack = const { Stack::new() };
+    assert_eq!(stack.capacity(), 0);
+    stack.uninit_slice(100, |slice: &mut [MaybeUninit<u64>]| {
+        assert_eq!(slice.len(), 100);
+    });
+    assert!(stack.capacity() >= 800);
+    stack.buffer(0..100u64, |items| {
+        assert_eq!(items.iter().sum::<u64>(), 4950);
+    });
+    assert_eq!(stack.bytes_in_use(), 0);
+}
+
+#[test]
+fn buffer_ffi_passes_ptr_and_len() {
+    // Stands in for a C function reading a buffer
+    unsafe extern "C" fn sum(ptr: *mut u32, len: usize) -> u32 {
+        let mut total = 0;
+        for i in 0..len {
+            total += *ptr.add(i);
+        }
+        total
+    }
+
+    let call = |ptr, len| unsafe { sum(ptr, len) };
+    assert_eq!(buffer_ffi(1..=100u32, call), 5050);
+    assert_eq!(buffer_ffi(std::iter::empty(), call), 0);
+
+    let stack = Stack::new();
+    assert_eq!(stack.buffer_ffi(1..=10u32, call), 55);
+}
+
+#[test]
+fn buffer_interleave_alternates() {
+    buffer_interleave([1, 3, 5].into_iter(), [2, 4].into_iter(), |items| {
+        assert_eq!(items, &[1, 2, 3, 4, 5]);
+    });
+    buffer_interleave(0..2, 10..15, |items| {
+        assert_eq!(items, &[0, 10, 1, 11, 12, 13, 14]);
+    });
+    let stack = Stack::new();
+    stack.buffer_interleave(0..0, 0..0, |items| {
+        assert!(items.is_empty());
+    });
+}
+
+#[test]
+fn buffer_interleave_panic_drops_items() {
+    let td = TestDrop::new();
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let a = (0..10).map(|_| td.new_item().1);
+        let b = (0..10).map(|i| {
+            if i == 5 {
+                panic!("b failed");
+            }
+            td.new_item().1
+        });
+        buffer_interleave(a, b, |_| unreachable!());
+    }));
+    assert!(result.is_err());
+    assert_eq!(td.num_tracked_items(), 11);
+    assert_eq!(td.num_dropped_items(), td.num_tracked_items());
+}
+
+#[test]
+fn uninit_slice_padding_reports_alignment() {
+    let stack = Stack::new();
+    stack.uninit_slice_padding(1, |bytes: &mut [MaybeUninit<u8>], padding| {
+        assert_eq!(padding, 0);
+        let end = bytes.as_ptr() as usize + 1;
+        stack.uninit_slice_padding(4, |words: &mut [MaybeUninit<u64>], padding| {
+            let start = words.as_ptr() as usize;
+            assert_eq!(start % std::mem::align_of::<u64>(), 0);
+            assert_eq!(start - end, padding);
+
+            // Already aligned after a u64, so no padding is needed
+            stack.uninit_slice_padding(1, |_: &mut [MaybeUninit<u32>], padding| {
+                assert_eq!(padding, 0);
+            });
+        });
+    });
+
+    uninit_slice_padding(0, |slice: &mut [MaybeUninit<u64>], padding| {
+        assert!(slice.is_empty());
+        assert_eq!(padding, 0);
+    });
+}
+
+#[test]
+fn uninit_slice_in_uses_backing() {
+    let mut backing = [MaybeUninit::<u8>::uninit(); 256];
+    let range = backing.as_ptr_range();
+    let range = range.start as usize..range.end as usize;
+
+    uninit_slice_in(&mut backing, 10, |slice: &mut [MaybeUninit<u64>]| {
+        assert_eq!(slice.len(), 10);
+        let start = slice.as_ptr() as usize;
+        assert_eq!(start % std::mem::align_of::<u64>(), 0);
+        assert!(range.contains(&start));
+        assert!(start + 80 <= range.end);
+        for (i, item) in slice.iter_mut().enumerate() {
+            *item = MaybeUninit::new(i as u64);
+        }
+    });
+
+    uninit_slice_in(&mut backing, 1000, |slice: &mut [MaybeUninit<()>]| {
+        assert_eq!(slice.len(), 1000);
+    });
+}
+
+#[test]
+#[should_panic(expected = "second-stack: backing region is too small")]
+fn uninit_slice_in_too_small() {
+    let mut backing = [MaybeUninit::<u8>::uninit(); 16];
+    uninit_slice_in(&mut backing, 3, |_: &mut [MaybeUninit<u64>]| {});
+}
+
+#[test]
+fn adjacent_same_type_slices() {
+    let stack = Stack::new();
+    // Warm up so that both slices fit in one allocation
+    stack.uninit_slice(64, |_: &mut [MaybeUninit<u64>]| {});
+
+    stack.uninit_slice_padding(4, |a: &mut [MaybeUninit<u64>], _| {
+        stack.uninit_slice_padding(4, |b: &mut [MaybeUninit<u64>], padding| {
+            assert_eq!(padding, 0);
+            assert_eq!(b.as_ptr(), a.as_ptr().wrapping_add(4));
+        });
+    });
+
+    stack.scope(|scope| {
+        let a = scope.buffer(0..4u64);
+        let b = scope.buffer(0..4u64);
+        assert_eq!(b.as_ptr(), a.as_ptr().wrapping_add(4));
+    });
+}
+
+#[test]
+fn scope_alloc_drops_in_reverse() {
+    let order = std::cell::RefCell::new(Vec::new());
+    struct Noisy<'a>(u32, &'a std::cell::RefCell<Vec<u32>>);
+    impl Drop for Noisy<'_> {
+        fn drop(&mut self) {
+            self.1.borrow_mut().push(self.0);
+        }
+    }
+
+    let stack = Stack::new();
+    stack.scope(|scope| {
+        let a = scope.alloc(Noisy(0, &order));
+        let items = scope.alloc_slice_with(3, |i| Noisy(i as u32 + 1, &order));
+        let b = scope.alloc(Noisy(4, &order));
+        a.0 += 10;
+        assert_eq!(items.len(), 3);
+        assert_eq!(b.0, 4);
+    });
+    assert_eq!(*order.borrow(), [4, 1, 2, 3, 10]);
+    assert_eq!(stack.bytes_in_use(), 0);
+
+    let td = TestDrop::new();
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        stack.scope(|scope| {
+            scope.alloc_slice_with(5, |i| {
+                if i == 2 {
+                    panic!("init failed");
+                }
+                td.new_item().1
+            });
+        })
+    }));
+    assert!(result.is_err());
+    assert_eq!(td.num_dropped_items(), 2);
+}
+
+#[test]
+fn uninit_slice_guard_restores() {
+    let mut stack = Stack::new();
+
+    let first = {
+        let mut guard = stack.uninit_slice_guard::<u32>(100);
+        assert_eq!(guard.len(), 100);
+        for (i, item) in guard.iter_mut().enumerate() {
+            *item = MaybeUninit::new(i as u32);
+        }
+        guard.as_ptr() as usize
+    };
+
+    // The same memory is handed out again after the guard is dropped
+    let guard = stack.uninit_slice_guard::<u32>(100);
+    assert_eq!(guard.as_ptr() as usize, first);
+    drop(guard);
+
+    assert_eq!(stack.uninit_slice_guard::<()>(1000).len(), 1000);
+    assert!(stack.uninit_slice_guard::<u64>(0).is_empty());
+
+    stack.uninit_slice(100, |slice: &mut [MaybeUninit<u32>]| {
+        assert_eq!(slice.as_ptr() as usize, first);
+    });
+}
+
+#[test]
+fn acquire_returns_items() {
+    fn evens(stack: &mut Stack, n: u32) -> StackSlice<'_, u32> {
+        stack.acquire((0..n).filter(|i| i % 2 == 0))
+    }
+
+    let mut stack = Stack::new();
+    let mut items = evens(&mut stack, 10);
+    items[0] = 10;
+    assert_eq!(&*items, &[10, 2, 4, 6, 8]);
+    drop(items);
+    assert_eq!(stack.bytes_in_use(), 0);
+
+    let td = TestDrop::new();
+    let items = stack.acquire((0..10).map(|_| td.new_item().1));
+    assert_eq!(items.len(), 10);
+    assert_eq!(td.num_dropped_items(), 0);
+    drop(items);
+    assert_eq!(td.num_dropped_items(), 10);
+    assert_eq!(stack.acquire(std::iter::repeat_n((), 5)).len(), 5);
+}
+
+#[test]
+fn reserve_raw_restores() {
+    let mut stack = Stack::with_capacity(4096);
+    let first = {
+        let guard = stack.reserve_raw(100, 64);
+        assert_eq!(guard.len(), 100);
+        assert_eq!(guard.as_ptr() as usize % 64, 0);
+        unsafe { guard.as_ptr().write_bytes(1, guard.len()) };
+        guard.as_ptr()
+    };
+    assert_eq!(stack.bytes_in_use(), 0);
+    assert_eq!(stack.reserve_raw(100, 64).as_ptr(), first);
+
+    let guard = stack.reserve_raw(0, 16);
+    assert!(guard.is_empty());
+    assert_eq!(guard.as_ptr() as usize % 16, 0);
+}
+
+#[test]
+fn buffer_releases_unused_reserve() {
+    let stack = Stack::new();
+    // Warm up so that everything below fits in one allocation
+    stack.uninit_slice(4096, |_: &mut [MaybeUninit<u64>]| {});
+
+    // Filter gives no useful size_hint, so buffering over-allocates
+    stack.buffer((0..1000u64).filter(|_| true), |items| {
+        assert_eq!(items.len(), 1000);
+        stack.uninit_slice(1000, |rest: &mut [MaybeUninit<u64>]| {
+            assert_eq!(
+                rest.as_ptr() as *const u64,
+                items.as_ptr().wrapping_add(items.len())
+            );
+        });
+    });
+
+    stack.scope(|scope| {
+        let a = scope.buffer((0..100u64).filter(|_| true));
+        let b = scope.buffer(0..100u64);
+        assert_eq!(b.as_ptr(), a.as_ptr().wrapping_add(a.len()));
+    });
+}
+
+#[test]
+fn buffer_with_capacity_empty_then_nested_growth() {
+    let stack = Stack::new();
+    stack.buffer_with_capacity(std::iter::empty::<u64>(), 4, |_| {
+        stack.uninit_slice::<u8, _, _>(100_000, |_| ());
+    });
+    assert_eq!(stack.bytes_in_use(), 0);
+}
+
+#[test]
+fn buffer_short_iterator_then_nested_growth() {
+    // Claims more items than it gives
+    struct Liar;
+    impl Iterator for Liar {
+        type Item = u64;
+        fn next(&mut self) -> Option<u64> {
+            None
+        }
+        fn size_hint(&self) -> (usize, Option<usize>) {
+            (8, None)
+        }
     }
+
+    let stack = Stack::new();
+    stack.buffer(Liar, |items| {
+        assert!(items.is_empty());
+        // Grows the stack, which frees the old allocation if it is idle
+        stack.uninit_slice(100_000, |_: &mut [MaybeUninit<u8>]| ());
+    });
+    assert_eq!(stack.bytes_in_use(), 0);
+
+    stack.buffer(Liar.chain(0..2), |items| {
+        assert_eq!(items, &[0, 1]);
+        stack.uninit_slice(1_000_000, |_: &mut [MaybeUninit<u8>]| ());
+    });
+    assert_eq!(stack.bytes_in_use(), 0);
+}
+
+#[test]
+fn buffer_str_concatenates() {
+    buffer_str(["Hello", ", ", "wörld", "!"].into_iter(), |s| {
+        assert_eq!(s, "Hello, wörld!");
+    });
+    buffer_str(std::iter::empty(), |s| assert_eq!(s, ""));
+
+    let stack = Stack::new();
+    let words: Vec<String> = (0..1000).map(|i| i.to_string()).collect();
+    stack.buffer_str(words.iter().map(String::as_str), |s| {
+        assert_eq!(s, words.concat());
+    });
+}
+
+#[test]
+fn cstr_is_nul_terminated() {
+    let stack = Stack::new();
+    let len = stack.cstr("hello", |s| {
+        assert_eq!(s.to_bytes_with_nul(), b"hello\0");
+        s.to_bytes().len()
+    });
+    assert_eq!(len, 5);
+    assert_eq!(stack.bytes_in_use(), 0);
+
+    cstr("", |s| assert_eq!(s.to_bytes_with_nul(), b"\0"));
+
+    let result = std::panic::catch_unwind(|| cstr("a\0b", |_| ()));
+    assert!(result.is_err());
+}
+
+#[test]
+fn buffer_join_separates() {
+    buffer_join(["a", "b", "c"].into_iter(), ", ", |s| {
+        assert_eq!(s, "a, b, c")
+    });
+    buffer_join(["only"].into_iter(), ", ", |s| assert_eq!(s, "only"));
+    buffer_join(std::iter::empty::<&str>(), ", ", |s| assert_eq!(s, ""));
+
+    let stack = Stack::new();
+    let words: Vec<String> = (0..1000).map(|i| i.to_string()).collect();
+    stack.buffer_join(words.iter(), "→", |s| {
+        assert_eq!(s, words.join("→"));
+    });
+    assert_eq!(stack.bytes_in_use(), 0);
+}
+
+#[test]
+fn uninit_slice_with_initializes() {
+    uninit_slice_with(
+        10,
+        |i| i * 2,
+        |items| {
+            assert_eq!(items, &[0, 2, 4, 6, 8, 10, 12, 14, 16, 18]);
+        },
+    );
+
+    let td = TestDrop::new();
+    Stack::new().uninit_slice_with(
+        100,
+        |_| td.new_item().1,
+        |items| assert_eq!(items.len(), 100),
+    );
+    assert_eq!(td.num_dropped_items(), 100);
+}
+
+#[test]
+fn with_moves_value_onto_stack() {
+    let stack = Stack::new();
+    let sum = stack.with([1u64; 512], |big| {
+        assert!(stack.bytes_in_use() >= 4096);
+        big[0] = 10;
+        big.iter().sum::<u64>()
+    });
+    assert_eq!(sum, 521);
+    assert_eq!(stack.bytes_in_use(), 0);
+
+    let td = TestDrop::new();
+    let (id, item) = td.new_item();
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        with(item, |_| panic!("f failed"));
+    }));
+    assert!(result.is_err());
+    td.assert_drop(id);
+}
+
+#[test]
+fn uninit_slice_with_panic_drops_initialized() {
+    let td = TestDrop::new();
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        uninit_slice_with(
+            10,
+            |i| {
+                if i == 4 {
+                    panic!("init failed");
+                }
+                td.new_item().1
+            },
+            |_| unreachable!(),
+        );
+    }));
+    assert!(result.is_err());
+    assert_eq!(td.num_tracked_items(), 4);
+    for id in 0..4 {
+        td.assert_drop(id);
+    }
+    // The stack is usable and restored after the panic
+    uninit_slice(10, |slice: &mut [MaybeUninit<u64>]| {
+        assert_eq!(slice.len(), 10)
+    });
+}
+
+#[test]
+fn uninit_slice_zeroed_is_zero() {
+    let stack = Stack::new();
+    // Dirty the memory first so that the zeroing is observable
+    stack.uninit_slice_with(100, |_| u64::MAX, |_| {});
+    stack.uninit_slice_zeroed(100, |items: &mut [u64]| {
+        assert!(items.iter().all(|&i| i == 0));
+    });
+
+    uninit_slice_zeroed(10, |items: &mut [[f32; 4]]| {
+        assert!(items.iter().flatten().all(|&f| f == 0.0));
+    });
+    uninit_slice_zeroed(10, |items: &mut [()]| assert_eq!(items.len(), 10));
+    uninit_slice_zeroed(0, |items: &mut [u8]| assert!(items.is_empty()));
+}
+
+#[test]
+fn buffer_results_short_circuits() {
+    let ok: Result<u32, ()> = buffer_results((0..10).map(Ok), |items| items.iter().sum());
+    assert_eq!(ok, Ok(45));
+
+    let td = TestDrop::new();
+    let mut polled = 0;
+    let items = (0..10).map(|i| {
+        polled += 1;
+        if i == 6 {
+            Err(i)
+        } else {
+            Ok(td.new_item().1)
+        }
+    });
+    let err = Stack::new().buffer_results(items, |_| unreachable!());
+    assert_eq!(err, Err(6));
+    assert_eq!(polled, 7);
+    assert_eq!(td.num_tracked_items(), 6);
+    assert_eq!(td.num_dropped_items(), 6);
+}
+
+#[test]
+#[should_panic(expected = "second-stack: allocation size overflow")]
+fn uninit_slice_size_overflow() {
+    // Panics before attempting to allocate anything
+    uninit_slice(usize::MAX, |_: &mut [MaybeUninit<u64>]| {});
+}
+
+#[test]
+#[should_panic(expected = "second-stack: allocation size overflow")]
+fn uninit_slice_larger_than_isize() {
+    // Fits in a usize, but no slice may be this large
+    let len = isize::MAX as usize / 2 + 1;
+    uninit_slice(len, |_: &mut [MaybeUninit<u16>]| {});
+}
+
+#[test]
+fn uninit_slice_fills_capacity_exactly() {
+    let stack = Stack::with_capacity(1 << 16);
+    stack.uninit_slice(1 << 13, |a: &mut [MaybeUninit<u64>]| {
+        assert_eq!(stack.remaining_bytes(), 0);
+        a[(1 << 13) - 1].write(1);
+        // The next slice does not fit, so goes to a new allocation
+        stack.uninit_slice(1, |b: &mut [MaybeUninit<u64>]| {
+            b[0].write(2);
+            assert_eq!(stack.capacity(), 1 << 17);
+        });
+    });
+    assert_eq!(stack.bytes_in_use(), 0);
+}
+
+#[test]
+fn bytes_in_use_and_capacity() {
+    let stack = Stack::new();
+    assert_eq!(stack.bytes_in_use(), 0);
+    assert_eq!(stack.capacity(), 0);
+    assert_eq!(stack.remaining_bytes(), 0);
+
+    stack.reserve(1000);
+    let capacity = stack.capacity();
+    assert!(capacity >= 1000);
+
+    stack.uninit_slice(100, |_: &mut [MaybeUninit<u8>]| {
+        assert_eq!(stack.bytes_in_use(), 100);
+        stack.uninit_slice(50, |_: &mut [MaybeUninit<u8>]| {
+            assert_eq!(stack.bytes_in_use(), 150);
+            assert_eq!(stack.remaining_bytes(), capacity - 150);
+        });
+        assert_eq!(stack.bytes_in_use(), 100);
+    });
+    assert_eq!(stack.bytes_in_use(), 0);
+    assert_eq!(stack.capacity(), capacity);
+
+    thread::spawn(|| {
+        assert_eq!(current_capacity(), 0);
+        assert_eq!(current_remaining_bytes(), 0);
+        buffer(0..10u8, |_| {
+            assert_eq!(current_bytes_in_use(), 10);
+            assert!(current_capacity() >= 10);
+            assert_eq!(current_remaining_bytes(), current_capacity() - 10);
+        });
+        assert_eq!(current_bytes_in_use(), 0);
+    })
+    .join()
+    .unwrap();
+}
+
+#[test]
+fn clear_frees_when_idle() {
+    let stack = Stack::new();
+    stack.reserve(1000);
+    stack.uninit_slice(10, |_: &mut [MaybeUninit<u32>]| {
+        // In use, so nothing is freed
+        stack.clear();
+        assert!(stack.capacity() >= 1000);
+    });
+    stack.clear();
+    assert_eq!(stack.capacity(), 0);
+
+    // Still usable afterwards
+    stack.buffer(0..10u32, |items| assert_eq!(items.len(), 10));
+}
+
+#[test]
+fn reset_to_capacity_shrinks_when_idle() {
+    let stack = Stack::with_capacity(1 << 20);
+    stack.uninit_slice(10, |_: &mut [MaybeUninit<u32>]| {
+        // In use, so nothing changes
+        stack.reset_to_capacity(1000);
+        assert_eq!(stack.capacity(), 1 << 20);
+    });
+    stack.reset_to_capacity(1000);
+    assert_eq!(stack.capacity(), 1024);
+
+    // Never grows
+    stack.reset_to_capacity(5000);
+    assert_eq!(stack.capacity(), 1024);
+
+    stack.buffer(0..10u32, |items| assert_eq!(items.len(), 10));
+    stack.reset_to_capacity(0);
+    assert_eq!(stack.capacity(), 0);
+}
+
+#[test]
+fn merge_adopts_larger_allocation() {
+    let stack = Stack::with_capacity(64);
+    let big = Stack::with_capacity(1 << 16);
+    big.buffer(0..100u32, |items| assert_eq!(items.len(), 100));
+    stack.merge(big);
+    assert_eq!(stack.capacity(), 1 << 16);
+    stack.buffer(0..1000u32, |items| assert_eq!(items[999], 999));
+
+    // A smaller stack is only freed
+    stack.merge(Stack::with_capacity(1024));
+    assert_eq!(stack.capacity(), 1 << 16);
+    stack.merge(Stack::new());
+    assert_eq!(stack.capacity(), 1 << 16);
+}
+
+#[cfg(feature = "stats")]
+#[test]
+fn peak_bytes_records_deepest_point() {
+    fn recurse(stack: &Stack, depth: usize) {
+        if depth == 0 {
+            stack.uninit_slice(1000, |_: &mut [MaybeUninit<u8>]| {});
+            return;
+        }
+        stack.uninit_slice(10, |_: &mut [MaybeUninit<u8>]| recurse(stack, depth - 1));
+    }
+
+    let stack = Stack::with_capacity(4096);
+    assert_eq!(stack.peak_bytes(), 0);
+    recurse(&stack, 5);
+    assert_eq!(stack.bytes_in_use(), 0);
+    assert_eq!(stack.peak_bytes(), 1050);
+
+    // Shallower use does not lower it
+    recurse(&stack, 1);
+    assert_eq!(stack.peak_bytes(), 1050);
+}
+
+#[cfg(feature = "stats")]
+#[test]
+fn measure_counts_bytes_used_by_call() {
+    let stack = Stack::with_capacity(4096);
+    stack.uninit_slice(100, |_: &mut [MaybeUninit<u8>]| {
+        let (value, used) = stack.measure(|| {
+            stack.uninit_slice(200, |_: &mut [MaybeUninit<u8>]| {});
+            stack.uninit_slice(50, |_: &mut [MaybeUninit<u8>]| 7)
+        });
+        assert_eq!(value, 7);
+        assert_eq!(used, 200);
+    });
+    // The lifetime peak is kept
+    assert_eq!(stack.peak_bytes(), 300);
+    let ((), used) = stack.measure(|| {});
+    assert_eq!(used, 0);
+    assert_eq!(stack.peak_bytes(), 300);
+
+    let ((), used) = measure(|| buffer(0..10u32, |_| ()));
+    assert_eq!(used, 40);
+}
+
+#[cfg(feature = "stats")]
+#[test]
+fn live_scopes_counts_nesting() {
+    fn recurse(stack: &Stack, depth: usize) {
+        assert_eq!(stack.live_scopes(), 5 - depth);
+        if depth == 0 {
+            return;
+        }
+        stack.uninit_slice(10, |_: &mut [MaybeUninit<u8>]| recurse(stack, depth - 1));
+    }
+
+    // Small enough to grow while slices are live
+    let stack = Stack::new();
+    recurse(&stack, 5);
+    assert_eq!(stack.live_scopes(), 0);
+
+    let stack = Stack::with_capacity(4096);
+    stack.scope(|scope| {
+        scope.alloc_slice::<u64>(4);
+        scope.buffer(0..100u32);
+        assert_eq!(stack.live_scopes(), 1);
+        stack.buffer(0..1000u32, |_| assert_eq!(stack.live_scopes(), 2));
+    });
+    assert_eq!(stack.live_scopes(), 0);
+}
+
+#[test]
+fn writer_io_write() {
+    use std::io::Write;
+
+    let stack = Stack::new();
+    let bytes = stack.writer(|writer| {
+        for i in 0..100 {
+            write!(writer, "{i},").unwrap();
+        }
+        // A nested allocation while the writer is not growing is fine
+        stack.uninit_slice(10, |_: &mut [MaybeUninit<u64>]| {
+            writer.write_all(b"end").unwrap();
+        });
+        writer.as_slice().to_vec()
+    });
+    let expected = (0..100).map(|i| format!("{i},")).collect::<String>() + "end";
+    assert_eq!(bytes, expected.as_bytes());
+    assert_eq!(stack.bytes_in_use(), 0);
+
+    second_stack::writer(|writer| {
+        writer.write_all(b"abc").unwrap();
+        assert_eq!(writer.as_slice(), b"abc");
+    });
+}
+
+#[test]
+fn format_to_stack() {
+    let stack = Stack::new();
+    let len = stack.format(format_args!("{}-{:>4}", "id", 7), |s| {
+        assert_eq!(s, "id-   7");
+        assert!(stack.bytes_in_use() >= s.len());
+        s.len()
+    });
+    assert_eq!(len, 7);
+    assert_eq!(stack.bytes_in_use(), 0);
+
+    second_stack::format(format_args!(""), |s| assert_eq!(s, ""));
+
+    stack.writer(|writer| {
+        use std::fmt::Write;
+        write!(writer, "{:?}", [1, 2]).unwrap();
+        assert_eq!(writer.as_slice(), b"[1, 2]");
+    });
+}
+
+#[test]
+#[should_panic(expected = "writer grown while a nested allocation is live")]
+fn writer_grow_under_nested_allocation() {
+    let stack = Stack::new();
+    stack.writer(|writer| {
+        stack.uninit_slice(10, |_: &mut [MaybeUninit<u64>]| {
+            writer.extend_from_slice(&[0; 100]);
+        });
+    });
+}
+
+#[test]
+fn build_vec_with_nested_allocations() {
+    let td = TestDrop::new();
+    let stack = Stack::new();
+    stack.build_vec(|vec| {
+        for i in 0..100 {
+            vec.push((i, td.new_item().1));
+            // Pushing grows the vec past these, which have been given back
+            stack.uninit_slice(i, |slice: &mut [MaybeUninit<u64>]| {
+                assert_eq!(slice.len(), i);
+            });
+        }
+        assert_eq!(vec.len(), 100);
+        assert!(vec.as_slice().iter().map(|(i, _)| *i).eq(0..100));
+        assert_eq!(td.num_dropped_items(), 0);
+    });
+    assert_eq!(td.num_dropped_items(), 100);
+    assert_eq!(stack.bytes_in_use(), 0);
+
+    build_vec(|vec| {
+        vec.push("a");
+        vec.push("b");
+        assert_eq!(vec.as_slice(), ["a", "b"]);
+    });
+}
+
+#[test]
+fn buffer_chunks_reuses_memory() {
+    let stack = Stack::new();
+    let mut chunks = Vec::new();
+    let mut bases = Vec::new();
+    stack.buffer_chunks(0..10u32, 4, |chunk| {
+        bases.push(chunk.as_ptr());
+        chunks.push(chunk.to_vec());
+    });
+    assert_eq!(chunks, [vec![0, 1, 2, 3], vec![4, 5, 6, 7], vec![8, 9]]);
+    assert!(bases.iter().all(|&base| base == bases[0]));
+    assert_eq!(stack.bytes_in_use(), 0);
+
+    let mut calls = 0;
+    buffer_chunks(0..8u32, 4, |chunk| {
+        assert_eq!(chunk.len(), 4);
+        calls += 1;
+    });
+    assert_eq!(calls, 2);
+
+    buffer_chunks(std::iter::empty::<u32>(), 4, |_| unreachable!());
+}
+
+#[test]
+fn buffer_chunks_drops_items() {
+    let td = TestDrop::new();
+    let stack = Stack::new();
+    stack.buffer_chunks((0..10).map(|_| td.new_item().1), 3, |chunk| {
+        assert_eq!(td.num_dropped_items() + chunk.len(), td.num_tracked_items());
+    });
+    assert_eq!(td.num_dropped_items(), 10);
+}
+
+#[test]
+fn buffer_batched_stops_early() {
+    let td = TestDrop::new();
+    let stack = Stack::new();
+    let mut taken = 0;
+    let mut batches = 0;
+    let items = (0..10).map(|_| {
+        taken += 1;
+        td.new_item().1
+    });
+    let flow = stack.buffer_batched(items, 3, |batch| {
+        batches += 1;
+        assert_eq!(batch.len(), 3);
+        if batches == 2 {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
+    });
+    assert_eq!(flow, ControlFlow::Break(()));
+    assert_eq!(taken, 6);
+    assert_eq!(td.num_dropped_items(), 6);
+    assert_eq!(stack.bytes_in_use(), 0);
+
+    let mut sums = Vec::new();
+    let flow = buffer_batched(0..10u32, 4, |batch| {
+        sums.push(batch.iter().sum::<u32>());
+        ControlFlow::Continue(())
+    });
+    assert_eq!(flow, ControlFlow::Continue(()));
+    // The partial last batch is delivered
+    assert_eq!(sums, [6, 22, 17]);
+}
+
+/// An iterator which gives 0..3, then None, then more items
+fn unfused() -> impl Iterator<Item = u32> {
+    let mut next = 0;
+    std::iter::from_fn(move || {
+        next += 1;
+        match next {
+            1..=3 => Some(next - 1),
+            4 => None,
+            _ => Some(100),
+        }
+    })
+}
+
+#[test]
+fn buffering_stops_at_first_none() {
+    let stack = Stack::new();
+    stack.buffer(unfused(), |items| assert_eq!(items, &[0, 1, 2]));
+
+    let mut chunks = Vec::new();
+    stack.buffer_chunks(unfused(), 3, |chunk| chunks.push(chunk.to_vec()));
+    assert_eq!(chunks, [[0, 1, 2]]);
+
+    let len = stack.try_buffer_bounded(unfused(), 3, |items| items.len());
+    assert_eq!(len, Ok(3));
+
+    stack.buffer_interleave(unfused(), 5..6, |items| {
+        assert_eq!(items, &[0, 5, 1, 2]);
+    });
+}
+
+#[test]
+fn buffer_windows_slides() {
+    let stack = Stack::new();
+    let mut sums = Vec::new();
+    stack.buffer_windows(1..=5u32, 3, |window| sums.push(window.iter().sum::<u32>()));
+    assert_eq!(sums, [6, 9, 12]);
+    assert_eq!(stack.bytes_in_use(), 0);
+
+    buffer_windows(0..2u32, 3, |_| unreachable!());
+}
+
+#[test]
+fn buffer_unzip_splits_pairs() {
+    let stack = Stack::new();
+    // Starts in a small allocation that is left behind when growing
+    stack.uninit_slice(1, |_: &mut [MaybeUninit<u8>]| {
+        let items = (0..10_000u32).filter(|_| true).map(|i| (i as u8, i));
+        stack.buffer_unzip(items, |a, b| {
+            assert_eq!(a.len(), 10_000);
+            assert!(a.iter().copied().eq((0..10_000u32).map(|i| i as u8)));
+            assert!(b.iter().copied().eq(0..10_000));
+        });
+    });
+    assert_eq!(stack.bytes_in_use(), 0);
+
+    buffer_unzip((0..3).map(|i| ((), i)), |a, b| {
+        assert_eq!(a.len(), 3);
+        assert_eq!(b, [0, 1, 2]);
+    });
+    buffer_unzip(std::iter::empty::<(u8, u64)>(), |a, b| {
+        assert!(a.is_empty() && b.is_empty());
+    });
+}
+
+#[test]
+fn buffer_unzip_panicking_iterator_drops_items() {
+    let td = TestDrop::new();
+    let stack = Stack::new();
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let items = (0..100).map(|i| {
+            if i == 50 {
+                panic!("in iterator");
+            }
+            (td.new_item().1, td.new_item().1)
+        });
+        stack.buffer_unzip(items, |_, _| unreachable!());
+    }));
+    assert!(result.is_err());
+    assert_eq!(td.num_tracked_items(), 100);
+    assert_eq!(td.num_dropped_items(), 100);
+    assert_eq!(stack.bytes_in_use(), 0);
+}
+
+#[test]
+fn copy_slice_leaves_original() {
+    let original = [3u32, 1, 2];
+    copy_slice(&original, |copy| {
+        copy.sort();
+        assert_eq!(copy, &[1, 2, 3]);
+    });
+    assert_eq!(original, [3, 1, 2]);
+    copy_slice(&[(); 4], |copy| assert_eq!(copy.len(), 4));
+    copy_slice::<u8, _, _>(&[], |copy| assert!(copy.is_empty()));
+}
+
+#[test]
+fn buffer_enumerate_keeps_positions() {
+    buffer_enumerate(["c", "a", "b"].into_iter(), |items| {
+        items.sort_by_key(|&(_, s)| s);
+        assert_eq!(items, &[(1, "a"), (2, "b"), (0, "c")]);
+    });
+    Stack::new().buffer_enumerate((10..20).filter(|i| i % 3 == 0), |items| {
+        assert_eq!(items, &[(0, 12), (1, 15), (2, 18)]);
+    });
+}
+
+#[test]
+fn buffer_scan_folds_state() {
+    buffer_scan(
+        1..=4u32,
+        0,
+        |sum, &i| *sum += i,
+        |items, sum| {
+            assert_eq!(items, &[1, 2, 3, 4]);
+            assert_eq!(sum, 10);
+        },
+    );
+
+    let td = TestDrop::new();
+    let stack = Stack::new();
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let items = (0..10).map(|_| td.new_item().1);
+        let state = td.new_item().1;
+        stack.buffer_scan(
+            items,
+            state,
+            |_, _| {
+                if td.num_tracked_items() == 6 {
+                    panic!("in step");
+                }
+            },
+            |_, _| unreachable!(),
+        );
+    }));
+    assert!(result.is_err());
+    // The state, the items buffered, and the item being stepped over
+    assert_eq!(td.num_tracked_items(), 6);
+    assert_eq!(td.num_dropped_items(), 6);
+    assert_eq!(stack.bytes_in_use(), 0);
+}
+
+#[test]
+fn buffer_extremum_by_key() {
+    let words = ["bb", "a", "ccc", "ddd", "e"];
+    buffer_max_by_key(
+        words.into_iter(),
+        |w| w.len(),
+        |items, max| {
+            assert_eq!(items.len(), 5);
+            assert_eq!(max, Some(3));
+        },
+    );
+    buffer_min_by_key(
+        words.into_iter(),
+        |w| w.len(),
+        |items, min| {
+            assert_eq!(items[min.unwrap()], "a");
+        },
+    );
+    buffer_max_by_key(
+        std::iter::empty::<u8>(),
+        |&i| i,
+        |items, max| {
+            assert!(items.is_empty());
+            assert_eq!(max, None);
+        },
+    );
+}
+
+#[test]
+fn buffer_take_while_drops_boundary() {
+    let td = TestDrop::new();
+    let mut items = (0..10).map(|i| (i, td.new_item().1));
+    buffer_take_while(
+        items.by_ref(),
+        |(i, _)| *i < 4,
+        |prefix| {
+            assert_eq!(prefix.len(), 4);
+            // The item which stopped it has already been dropped
+            assert_eq!(td.num_dropped_items(), 1);
+        },
+    );
+    assert_eq!(td.num_dropped_items(), 5);
+    assert_eq!(items.next().unwrap().0, 5);
+}
+
+#[test]
+fn buffer_skip_while_keeps_rest() {
+    buffer_skip_while(
+        [1u32, 2, 5, 1, 2].into_iter(),
+        |&i| i < 3,
+        |rest| {
+            assert_eq!(rest, &[5, 1, 2]);
+        },
+    );
+    buffer_skip_while(0..10u32, |_| true, |rest| assert!(rest.is_empty()));
+}
+
+#[test]
+fn buffer_flatten_concatenates() {
+    let rows = [vec![1u32, 2], vec![], vec![3, 4, 5]];
+    buffer_flatten(rows.iter().map(|row| row.iter().copied()), |items| {
+        assert_eq!(items, &[1, 2, 3, 4, 5]);
+    });
+    buffer_flatten((0..3).map(|i| 0..i), |items| {
+        assert_eq!(items, &[0, 0, 1]);
+    });
+}
+
+#[test]
+fn buffer_flatten_panicking_inner_iterator_drops_items() {
+    let td = TestDrop::new();
+    let stack = Stack::new();
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let rows = (0..10).map(|row| {
+            let td = &td;
+            (0..10).map(move |i| {
+                if row == 5 && i == 5 {
+                    panic!("in inner iterator");
+                }
+                td.new_item().1
+            })
+        });
+        stack.buffer_flatten(rows, |_| unreachable!());
+    }));
+    assert!(result.is_err());
+    assert_eq!(td.num_tracked_items(), 55);
+    assert_eq!(td.num_dropped_items(), 55);
+    assert_eq!(stack.bytes_in_use(), 0);
+}
+
+#[test]
+fn buffered_slice_splits_into_chunks() {
+    fn process(chunk: &mut [u32]) -> u32 {
+        // Each helper makes its own use of the stack above the slice
+        let sum = buffer_copy(chunk.iter().map(|i| i * 2), |doubled| doubled.iter().sum());
+        for i in chunk.iter_mut() {
+            *i *= 2;
+        }
+        sum
+    }
+
+    buffer(0..100u32, |items| {
+        let (a, b) = items.split_at_mut(50);
+        let mut sum = 0;
+        for (a, b) in a.chunks_mut(10).zip(b.chunks_mut(10)) {
+            sum += process(a);
+            sum += process(b);
+        }
+        assert_eq!(sum, 9900);
+        assert!(items.iter().copied().eq((0..100).map(|i| i * 2)));
+    });
+}
+
+#[test]
+fn catch_buffer_returns_panics() {
+    let td = TestDrop::new();
+    let stack = Stack::new();
+    let items = (0..100).map(|i| {
+        if i == 50 {
+            panic!("in iterator");
+        }
+        td.new_item().1
+    });
+    let result = stack.catch_buffer(items, |_| unreachable!());
+    assert_eq!(result.unwrap_err().downcast_ref(), Some(&"in iterator"));
+    assert_eq!(td.num_tracked_items(), 50);
+    assert_eq!(td.num_dropped_items(), 50);
+    assert_eq!(stack.bytes_in_use(), 0);
+
+    // The stack is still usable after a caught panic
+    let result = stack.catch_buffer(0..10u32, |items| items.iter().sum::<u32>());
+    assert_eq!(result.unwrap(), 45);
+
+    let result = catch_buffer(0..10u32, |_| -> u32 { panic!("in closure") });
+    assert!(result.is_err());
+}
+
+#[test]
+fn writer_with_capacity_pushes_items() {
+    let stack = Stack::new();
+    stack.writer_with_capacity(4, |writer| {
+        assert!(writer.is_empty());
+        writer.push(1u32);
+        writer.extend_from_slice(&[2, 3, 4]);
+        // Grows past the capacity
+        writer.push(5);
+        assert_eq!(writer.len(), 5);
+        writer.as_mut_slice()[0] = 0;
+        assert_eq!(writer.as_slice(), &[0, 2, 3, 4, 5]);
+    });
+    assert_eq!(stack.bytes_in_use(), 0);
+
+    let td = TestDrop::new();
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        writer_with_capacity(2, |writer| {
+            for _ in 0..10 {
+                writer.push(td.new_item().1);
+            }
+            panic!("in closure");
+        })
+    }));
+    assert!(result.is_err());
+    assert_eq!(td.num_tracked_items(), 10);
+    assert_eq!(td.num_dropped_items(), 10);
+}
+
+#[test]
+fn with_thread_stack_redirects() {
+    thread::spawn(|| {
+        let outer = Stack::new();
+        let inner = Stack::new();
+        with_thread_stack(&outer, || {
+            buffer(0..100u32, |items| {
+                assert_eq!(outer.bytes_in_use(), 400);
+                with_thread_stack(&inner, || {
+                    uninit_slice(10, |_: &mut [MaybeUninit<u64>]| {
+                        assert_eq!(current_bytes_in_use(), 80);
+                        assert_eq!(inner.bytes_in_use(), 80);
+                    });
+                });
+                assert_eq!(current_bytes_in_use(), 400);
+                assert_eq!(items[99], 99);
+            });
+        });
+        assert!(outer.capacity() >= 400);
+        assert!(inner.capacity() >= 80);
+
+        // Put back when unwinding
+        let result = std::panic::catch_unwind(|| {
+            with_thread_stack(&Stack::new(), || panic!("in closure"));
+        });
+        assert!(result.is_err());
+        uninit_slice(1, |_: &mut [MaybeUninit<u8>]| {
+            assert_eq!(current_bytes_in_use(), 1);
+            assert_eq!(outer.bytes_in_use(), 0);
+        });
+    })
+    .join()
+    .unwrap();
+}
+
+#[test]
+fn is_borrowed_while_slice_is_live() {
+    thread::spawn(|| {
+        assert!(!is_borrowed());
+        uninit_slice(10, |_: &mut [MaybeUninit<u32>]| {
+            assert!(is_borrowed());
+        });
+        assert!(!is_borrowed());
+        buffer(0..4u8, |_| assert!(is_borrowed()));
+        assert!(!is_borrowed());
+    })
+    .join()
+    .unwrap();
+}
+
+#[test]
+fn try_buffer_bounded_limits_items() {
+    let td = TestDrop::new();
+    let stack = Stack::new();
+    let result = stack.try_buffer_bounded((0..).map(|_| td.new_item().1), 100, |_| unreachable!());
+    assert_eq!(result, Err(BufferTooLarge { limit: 100 }));
+    assert_eq!(td.num_dropped_items(), td.num_tracked_items());
+    assert_eq!(stack.bytes_in_use(), 0);
+
+    let sum = try_buffer_bounded(0..100u32, 100, |items| items.iter().sum::<u32>());
+    assert_eq!(sum, Ok(4950));
+    let len = try_buffer_bounded(std::iter::empty::<u8>(), 0, |items| items.len());
+    assert_eq!(len, Ok(0));
+}
+
+#[test]
+fn buffer_to_arc_moves_items() {
+    let td = TestDrop::new();
+    let stack = Stack::new();
+    let arc = stack.buffer_to_arc((0..10).map(|i| (9 - i, td.new_item().1)), |items| {
+        items.sort_by_key(|item| item.0);
+    });
+    assert!(arc.iter().map(|item| item.0).eq(0..10));
+    assert_eq!(stack.bytes_in_use(), 0);
+    assert_eq!(td.num_dropped_items(), 0);
+    drop(arc);
+    assert_eq!(td.num_dropped_items(), 10);
+
+    let rc = buffer_to_rc(0..3u32, |items| items.reverse());
+    assert_eq!(*rc, [2, 1, 0]);
+}
+
+#[test]
+fn buffer_dedup_drops_repeats_once() {
+    let td = TestDrop::new();
+    let stack = Stack::new();
+    let items = [1, 1, 2, 3, 3, 3, 1].map(|key| (key, td.new_item().1));
+    stack.buffer_dedup_by_key(
+        items.into_iter(),
+        |item| item.0,
+        |items| {
+            assert!(items.iter().map(|item| item.0).eq([1, 2, 3, 1]));
+            assert_eq!(td.num_dropped_items(), 3);
+        },
+    );
+    assert_eq!(td.num_dropped_items(), 7);
+    assert_eq!(stack.bytes_in_use(), 0);
+
+    buffer_dedup("aabccb".chars(), |chars| {
+        assert_eq!(chars, ['a', 'b', 'c', 'b'])
+    });
+    buffer_dedup(std::iter::empty::<u8>(), |items| assert!(items.is_empty()));
+}
+
+#[test]
+fn buffer_partition_splits() {
+    let stack = Stack::new();
+    stack.buffer_partition(
+        0..10u32,
+        |i| i % 3 == 0,
+        |matching, rest| {
+            assert_eq!(matching, [0, 3, 6, 9]);
+            rest.sort();
+            assert_eq!(rest, [1, 2, 4, 5, 7, 8]);
+        },
+    );
+    assert_eq!(stack.bytes_in_use(), 0);
+
+    let td = TestDrop::new();
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let items = (0..10).map(|i| (i, td.new_item().1));
+        buffer_partition(
+            items,
+            |item| {
+                assert!(item.0 < 5, "in pred");
+                true
+            },
+            |_, _| unreachable!(),
+        );
+    }));
+    assert!(result.is_err());
+    assert_eq!(td.num_dropped_items(), 10);
+}
+
+#[test]
+#[cfg(all(unix, feature = "madvise"))]
+fn release_to_os_keeps_allocation() {
+    let stack = Stack::with_capacity(1 << 20);
+    stack.buffer(0..(1 << 18), |items: &mut [u32]| {
+        // Nothing is released while a slice is live
+        stack.release_to_os();
+        assert_eq!(items[1000], 1000);
+    });
+    stack.release_to_os();
+    assert_eq!(stack.capacity(), 1 << 20);
+    stack.buffer(0..(1 << 18), |items: &mut [u32]| {
+        assert_eq!(items[1000], 1000);
+    });
+
+    release_to_os();
+}
+
+#[test]
+#[cfg(all(unix, feature = "prefault"))]
+fn prefault_when_idle() {
+    let stack = Stack::with_capacity((1 << 20) + 1);
+    stack.uninit_slice(10, |slice: &mut [MaybeUninit<u8>]| {
+        // Nothing is touched while a slice is live
+        stack.prefault();
+        assert_eq!(slice.len(), 10);
+    });
+    stack.prefault();
+    stack.buffer(0..(1 << 18), |items: &mut [u32]| {
+        assert_eq!(items[1000], 1000);
+    });
+
+    warm_up(1 << 16);
+    prefault();
+}
+
+#[test]
+#[cfg(feature = "bytemuck")]
+fn buffer_bytes_views_items() {
+    let stack = Stack::new();
+    stack.buffer_bytes([1u32, 2].into_iter(), |bytes| {
+        assert_eq!(bytes.len(), 8);
+        assert_eq!(bytes[..4], 1u32.to_ne_bytes());
+    });
+    buffer_bytes(std::iter::empty::<u64>(), |bytes| assert!(bytes.is_empty()));
+    buffer_bytes(
+        std::iter::repeat_n((), 3),
+        |bytes| assert!(bytes.is_empty()),
+    );
+    assert_eq!(stack.bytes_in_use(), 0);
+}
+
+#[test]
+fn len_and_is_empty() {
+    let stack = Stack::new();
+    assert!(stack.is_empty());
+    stack.uninit_slice(10, |_: &mut [MaybeUninit<u16>]| {
+        assert_eq!(stack.len(), 20);
+        assert!(!stack.is_empty());
+    });
+    assert_eq!(stack.len(), 0);
+    assert!(stack.is_empty());
+}
+
+#[test]
+fn buffer_copy_from_slice() {
+    let stack = Stack::new();
+    let data = [3u16, 1, 4, 1, 5];
+    stack.buffer_copy(data.iter().copied(), |items| {
+        assert_eq!(items, data);
+        assert_eq!(stack.bytes_in_use(), 10);
+    });
+    assert_eq!(stack.bytes_in_use(), 0);
+
+    // An iterator which reports the wrong length
+    struct Short(u8);
+    impl Iterator for Short {
+        type Item = u8;
+        fn next(&mut self) -> Option<u8> {
+            self.0 = self.0.checked_sub(1)?;
+            Some(self.0)
+        }
+        fn size_hint(&self) -> (usize, Option<usize>) {
+            (10, Some(10))
+        }
+    }
+    impl ExactSizeIterator for Short {}
+    buffer_copy(Short(3), |items| assert_eq!(items, [2, 1, 0]));
+}
+
+#[test]
+fn uninit_slice_commit_gives_back_tail() {
+    let stack = Stack::new();
+    stack.uninit_slice_commit(
+        100,
+        |slots: &mut [MaybeUninit<u32>]| {
+            for (i, slot) in slots[..10].iter_mut().enumerate() {
+                slot.write(i as u32);
+            }
+            10
+        },
+        |prefix| {
+            assert_eq!(prefix.len(), 10);
+            assert_eq!(stack.bytes_in_use(), 40);
+            // A nested slice reuses the memory after the prefix
+            stack.uninit_slice(1, |nested: &mut [MaybeUninit<u32>]| {
+                assert_eq!(nested.as_ptr(), prefix.as_ptr().wrapping_add(10));
+            });
+            assert_eq!(unsafe { prefix[9].assume_init() }, 9);
+        },
+    );
+    assert_eq!(stack.bytes_in_use(), 0);
+
+    uninit_slice_commit(
+        8,
+        |_: &mut [MaybeUninit<u8>]| 0,
+        |prefix| {
+            assert!(prefix.is_empty());
+        },
+    );
+}
+
+#[test]
+fn uninit_slice_commit_nothing_then_nested_growth() {
+    let stack = Stack::new();
+    stack.uninit_slice_commit::<u64, _, _, _>(
+        4,
+        |_| 0,
+        |_| {
+            stack.uninit_slice::<u8, _, _>(100_000, |_| ());
+        },
+    );
+    assert_eq!(stack.bytes_in_use(), 0);
+
+    stack.uninit_slice_commit::<u64, _, _, _>(
+        4,
+        |_| 0,
+        |_| {
+            stack.clear();
+            stack.reserve(1_000_000);
+        },
+    );
+    assert_eq!(stack.bytes_in_use(), 0);
+}
+
+#[test]
+#[should_panic(expected = "committed more than the slice's len")]
+fn uninit_slice_commit_too_many() {
+    uninit_slice_commit(8, |_: &mut [MaybeUninit<u8>]| 9, |_| {});
+}
+
+#[test]
+fn over_aligned_items_are_aligned() {
+    #[derive(Copy, Clone)]
+    #[repr(align(32))]
+    struct Lanes([f32; 8]);
+
+    let stack = Stack::with_capacity(4096);
+    // Start at an odd offset so that padding is needed
+    stack.uninit_slice(3, |_: &mut [MaybeUninit<u8>]| {
+        stack.uninit_slice(4, |slice: &mut [MaybeUninit<Lanes>]| {
+            assert_eq!(slice.as_ptr() as usize % 32, 0);
+            for (i, lanes) in slice.iter_mut().enumerate() {
+                lanes.write(Lanes([i as f32; 8]));
+            }
+        });
+        stack.uninit(|lanes: &mut MaybeUninit<Lanes>| {
+            assert_eq!(lanes.as_ptr() as usize % 32, 0);
+            lanes.write(Lanes([1.0; 8]));
+        });
+        stack.buffer((0..5).map(|i| Lanes([i as f32; 8])), |items| {
+            assert_eq!(items.as_ptr() as usize % 32, 0);
+            assert_eq!(items[4].0, [4.0; 8]);
+        });
+    });
+
+    uninit_array(|lanes: &mut [MaybeUninit<Align64<u8>>; 3]| {
+        assert_eq!(lanes.as_ptr() as usize % 64, 0);
+    });
+}
+
+#[test]
+fn uninit_slice_aligned_is_aligned() {
+    let stack = Stack::new();
+    for align in [1, 8, 32, 64, 4096] {
+        // Start at an odd offset so that padding is needed
+        stack.uninit_slice(3, |_: &mut [MaybeUninit<u8>]| {
+            stack.uninit_slice_aligned(100, align, |slice: &mut [MaybeUninit<u8>]| {
+                assert_eq!(slice.len(), 100);
+                assert_eq!(slice.as_ptr() as usize % align, 0);
+                for item in slice.iter_mut() {
+                    item.write(1);
+                }
+            });
+        });
+    }
+    assert_eq!(stack.bytes_in_use(), 0);
+
+    // The alignment of T is used when it is greater
+    uninit_slice_aligned(10, 1, |slice: &mut [MaybeUninit<u64>]| {
+        assert_eq!(slice.as_ptr() as usize % std::mem::align_of::<u64>(), 0);
+    });
+    uninit_slice_aligned(0, 64, |slice: &mut [MaybeUninit<u8>]| {
+        assert_eq!(slice.as_ptr() as usize % 64, 0);
+    });
+}
+
+#[test]
+fn with_raw_bytes_for_ffi() {
+    let stack = Stack::new();
+    stack.uninit_slice(3, |_: &mut [MaybeUninit<u8>]| {
+        let first = stack.with_raw_bytes(100, 32, |ptr, len| {
+            assert_eq!(ptr as usize % 32, 0);
+            assert_eq!(len, 100);
+            // Stands in for a C function that fills the buffer
+            unsafe { std::ptr::write_bytes(ptr, 7, len) };
+            unsafe { *ptr }
+        });
+        assert_eq!(first, 7);
+    });
+    assert_eq!(stack.bytes_in_use(), 0);
+
+    with_raw_bytes(0, 16, |ptr, len| {
+        assert_eq!(ptr as usize % 16, 0);
+        assert_eq!(len, 0);
+    });
+}
+
+#[test]
+fn alloc_uninit_aligned_guards() {
+    let stack = Stack::with_capacity(1024);
+    unsafe {
+        let (bytes_guard, bytes) = stack.alloc_uninit_aligned::<u8>(3, 1);
+        let (floats_guard, floats) = stack.alloc_uninit_aligned::<f32>(16, 64);
+        assert_eq!(floats.as_ptr() as usize % 64, 0);
+        bytes[2].write(1);
+        floats[15].write(1.0);
+        let (empty_guard, empty) = stack.alloc_uninit_aligned::<u64>(0, 8);
+        assert!(empty.is_empty());
+
+        drop(empty_guard);
+        drop(floats_guard);
+        assert!(stack.bytes_in_use() >= 3);
+        drop(bytes_guard);
+    }
+    assert_eq!(stack.bytes_in_use(), 0);
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "non-LIFO restore detected")]
+fn out_of_order_restore_is_detected() {
+    let stack = Stack::with_capacity(1024);
+    unsafe {
+        let (first, _) = stack.alloc_uninit_aligned::<u8>(3, 1);
+        let (second, _) = stack.alloc_uninit_aligned::<u8>(5, 1);
+        drop(first);
+        drop(second);
+    }
+}
+
+#[test]
+fn over_aligned_slices() {
+    let stack = Stack::new();
+    // Offsets of the top of the stack that need differing amounts of padding
+    for offset in [0, 1, 17, 63] {
+        stack.uninit_slice(offset, |_: &mut [MaybeUninit<u8>]| {
+            for len in [1, 3, 64, 1000] {
+                stack.uninit_slice(len, |slice: &mut [MaybeUninit<Align64<u8>>]| {
+                    assert_eq!(slice.as_ptr() as usize % 64, 0);
+                    for (i, item) in slice.iter_mut().enumerate() {
+                        item.write(Align64(i as u8));
+                    }
+                    stack.buffer((0..len).map(|i| Align32(i as u16)), |items| {
+                        assert_eq!(items.as_ptr() as usize % 32, 0);
+                        assert!(items.iter().map(|item| item.0 as usize).eq(0..len));
+                    });
+                    for (i, item) in slice.iter().enumerate() {
+                        assert_eq!(unsafe { item.assume_init() }, Align64(i as u8));
+                    }
+                });
+            }
+        });
+    }
+    assert_eq!(stack.bytes_in_use(), 0);
+
+    // Growing a fresh stack may move the items to an allocation
+    // with a different alignment
+    let items = (0..10_000u32).filter(|i| i % 3 != 0).map(Align64);
+    Stack::new().buffer(items, |items| {
+        assert_eq!(items.as_ptr() as usize % 64, 0);
+        assert!(items
+            .iter()
+            .map(|item| item.0)
+            .eq((0..10_000).filter(|i| i % 3 != 0)));
+    });
+}
+
+#[test]
+#[cfg(any(feature = "poison", all(debug_assertions, not(feature = "zeroize"))))]
+#[cfg_attr(miri, ignore)] // Reading through the stale pointer is the point
+fn given_back_memory_is_poisoned() {
+    let stack = Stack::new();
+    stack.uninit_slice(8, |_: &mut [MaybeUninit<u8>]| {
+        let stale = stack.uninit_slice(16, |slice: &mut [MaybeUninit<u8>]| {
+            for item in slice.iter_mut() {
+                item.write(0);
+            }
+            slice.as_ptr() as *const u8
+        });
+        // The stack still holds the memory, so this read is of a live allocation
+        let bytes = unsafe { std::slice::from_raw_parts(stale, 16) };
+        assert!(bytes.iter().all(|&byte| byte == POISON));
+    });
+}
+
+#[test]
+#[cfg(all(feature = "zeroize", not(feature = "poison")))]
+#[cfg_attr(miri, ignore)] // Reading through the stale pointer is the point
+fn given_back_memory_is_zeroed() {
+    let stack = Stack::new();
+    stack.uninit_slice(8, |_: &mut [MaybeUninit<u8>]| {
+        let stale = stack.uninit_slice(16, |slice: &mut [MaybeUninit<u8>]| {
+            for item in slice.iter_mut() {
+                item.write(0xA5);
+            }
+            slice.as_ptr() as *const u8
+        });
+        // The stack still holds the memory, so this read is of a live allocation
+        let bytes = unsafe { std::slice::from_raw_parts(stale, 16) };
+        assert!(bytes.iter().all(|&byte| byte == 0));
+    });
+}
+
+#[test]
+fn zst_with_drop() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static DROPS: AtomicUsize = AtomicUsize::new(0);
+    struct Zst;
+    impl Drop for Zst {
+        fn drop(&mut self) {
+            DROPS.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    let stack = Stack::new();
+    stack.buffer((0..1000).map(|_| Zst), |items| {
+        assert_eq!(items.len(), 1000);
+        assert_eq!(DROPS.load(Ordering::Relaxed), 0);
+    });
+    assert_eq!(DROPS.load(Ordering::Relaxed), 1000);
+
+    stack.uninit_slice(usize::MAX, |slice: &mut [MaybeUninit<Zst>]| {
+        assert_eq!(slice.len(), usize::MAX);
+        slice[0].write(Zst);
+    });
+    // Items of an uninit slice are never dropped
+    assert_eq!(DROPS.load(Ordering::Relaxed), 1000);
+    assert_eq!(stack.capacity(), 0);
+}
+
+#[test]
+fn checkpoint_rollback() {
+    let mut stack = Stack::with_capacity(1024);
+    let mut outer = stack.checkpoint();
+    std::mem::forget(outer.uninit_slice_guard::<u64>(4));
+    assert_eq!(outer.bytes_in_use(), 32);
+
+    for attempt in 0..3 {
+        let mut inner = outer.checkpoint();
+        // A speculative allocation that is abandoned
+        let mut scratch = inner.uninit_slice_guard::<u32>(10 + attempt);
+        scratch[0].write(1);
+        std::mem::forget(scratch);
+        assert!(inner.bytes_in_use() > 32);
+        inner.rollback();
+        assert_eq!(outer.bytes_in_use(), 32);
+    }
+    outer.rollback();
+    assert_eq!(stack.bytes_in_use(), 0);
+
+    // Growing to a new allocation since the checkpoint
+    let mut checkpoint = stack.checkpoint();
+    std::mem::forget(checkpoint.uninit_slice_guard::<u8>(10_000));
+    checkpoint.rollback();
+    assert_eq!(stack.bytes_in_use(), 0);
+    assert!(stack.capacity() >= 10_000);
+
+    // Nothing taken since the checkpoint
+    stack.checkpoint().rollback();
+    assert_eq!(stack.bytes_in_use(), 0);
+}
+
+#[test]
+#[cfg_attr(miri, ignore)] // Leaks the superseded allocation, as documented
+fn checkpoint_rollback_after_move() {
+    let mut stack = Stack::with_capacity(64);
+    let mut checkpoint = stack.checkpoint();
+    // The first allocation is still held when the stack moves on
+    std::mem::forget(checkpoint.uninit_slice_guard::<u8>(8));
+    std::mem::forget(checkpoint.uninit_slice_guard::<u8>(10_000));
+    assert!(checkpoint.capacity() >= 10_000);
+    checkpoint.rollback();
+
+    // Only the new allocation is given back, and it is whole again
+    assert_eq!(stack.bytes_in_use(), 0);
+    let capacity = stack.capacity();
+    assert!(capacity >= 10_000);
+    stack.uninit_slice(capacity, |slice: &mut [MaybeUninit<u8>]| {
+        assert_eq!(slice.len(), capacity);
+    });
+    assert_eq!(stack.capacity(), capacity);
+}
+
+#[test]
+fn boxed_drops_value() {
+    let td = TestDrop::new();
+    let mut stack = Stack::new();
+    {
+        let (id, item) = td.new_item();
+        let mut boxed = stack.boxed(([0u64; 1000], item));
+        boxed.0[999] = 1;
+        assert_eq!(boxed.0.iter().sum::<u64>(), 1);
+        td.assert_no_drop(id);
+    }
+    assert_eq!(td.num_dropped_items(), 1);
+    assert_eq!(stack.bytes_in_use(), 0);
+
+    // Also dropped when unwinding
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let _boxed = stack.boxed(td.new_item().1);
+        panic!("while boxed");
+    }));
+    assert!(result.is_err());
+    assert_eq!(td.num_dropped_items(), 2);
+    assert_eq!(stack.bytes_in_use(), 0);
+}
+
+#[test]
+fn stack_in_default_struct() {
+    #[derive(Default)]
+    struct Worker {
+        scratch: Stack,
+        runs: u32,
+    }
+
+    let mut worker = Worker::default();
+    worker.runs += 1;
+    worker
+        .scratch
+        .buffer(0..4u8, |items| assert_eq!(items, [0, 1, 2, 3]));
+    assert_eq!(worker.scratch.bytes_in_use(), 0);
+}
+
+#[test]
+fn builder_min_capacity() {
+    let stack = Stack::builder().min_capacity(256).build();
+    assert_eq!(stack.capacity(), 0);
+    stack.uninit_slice(1, |_: &mut [MaybeUninit<u8>]| {});
+    assert_eq!(stack.capacity(), 256);
+
+    // Growing past the first allocation still doubles
+    stack.uninit_slice(300, |_: &mut [MaybeUninit<u8>]| {});
+    assert_eq!(stack.capacity(), 512);
+
+    let stack = Stack::builder().min_capacity(8).build();
+    stack.uninit_slice(1, |_: &mut [MaybeUninit<u8>]| {});
+    assert_eq!(stack.capacity(), 8);
+}
+
+#[test]
+fn builder_growth_factor() {
+    let stack = Stack::builder().growth_factor(1.5).build();
+    stack.uninit_slice(100, |_: &mut [MaybeUninit<u8>]| {});
+    assert_eq!(stack.capacity(), 144);
+    stack.uninit_slice(150, |_: &mut [MaybeUninit<u8>]| {});
+    assert_eq!(stack.capacity(), 216);
+}
+
+#[test]
+fn builder_growth_policy() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct Step;
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+    impl GrowthPolicy for Step {
+        fn next_capacity(&self, current: usize, _required: usize) -> usize {
+            CALLS.fetch_add(1, Ordering::Relaxed);
+            current + 1000
+        }
+    }
+
+    let stack = Stack::builder().growth_policy(&Step).build();
+    stack.uninit_slice(100, |_: &mut [MaybeUninit<u8>]| {});
+    assert_eq!(stack.capacity(), 1000);
+    // Raised to what is required
+    stack.uninit_slice(5000, |_: &mut [MaybeUninit<u8>]| {});
+    assert_eq!(stack.capacity(), 5000);
+    assert_eq!(CALLS.load(Ordering::Relaxed), 2);
+
+    let stack = Stack::builder().growth_policy(&Exact).build();
+    stack.uninit_slice(100, |_: &mut [MaybeUninit<u8>]| {});
+    assert_eq!(stack.capacity(), 100);
+
+    static ONE_AND_A_HALF: Factor = Factor::new(1.5);
+    let stack = Stack::builder().growth_policy(&ONE_AND_A_HALF).build();
+    stack.uninit_slice(100, |_: &mut [MaybeUninit<u8>]| {});
+    assert_eq!(stack.capacity(), 144);
+
+    let stack = Stack::builder().growth_policy(&Doubling).build();
+    stack.uninit_slice(100, |_: &mut [MaybeUninit<u8>]| {});
+    assert_eq!(stack.capacity(), 128);
+}
+
+#[test]
+#[should_panic(expected = "growth factor must be greater than 1")]
+fn growth_policy_factor_too_small() {
+    let _ = Factor::new(1.0);
+}
+
+#[test]
+fn builder_heap_threshold() {
+    let stack = Stack::builder().heap_threshold(1024).build();
+    stack.uninit_slice(100, |_: &mut [MaybeUninit<u64>]| {
+        assert_eq!(stack.bytes_in_use(), 800);
+        stack.uninit_slice(1000, |slice: &mut [MaybeUninit<u64>]| {
+            assert_eq!(slice.len(), 1000);
+            slice[999].write(1);
+            // Nothing more was taken from the stack
+            assert_eq!(stack.bytes_in_use(), 800);
+        });
+        let len = stack.try_uninit_slice(200, |slice: &mut [MaybeUninit<u64>]| slice.len());
+        assert_eq!(len, Ok(200));
+    });
+    assert_eq!(stack.capacity(), 1024);
+}
+
+#[test]
+fn uninit_slice2_heap_threshold() {
+    let stack = Stack::builder().heap_threshold(1024).build();
+    stack.uninit_slice2::<u8, u8, _, _>(1 << 20, 0, |a, b| {
+        assert_eq!((a.len(), b.len()), (1 << 20, 0));
+    });
+    stack.uninit_slice2::<u8, u64, _, _>(0, 1 << 20, |a, b| {
+        assert_eq!((a.len(), b.len()), (0, 1 << 20));
+    });
+    assert_eq!(stack.capacity(), 0);
+
+    // Only the small slice takes room on the stack
+    stack.uninit_slice2::<u8, u8, _, _>(1 << 20, 4, |a, b| {
+        assert_eq!((a.len(), b.len()), (1 << 20, 4));
+        assert_eq!(stack.bytes_in_use(), 4);
+    });
+    assert!(stack.capacity() <= 1024);
+}
+
+#[test]
+#[cfg(feature = "adaptive")]
+fn builder_decay_window() {
+    let stack = Stack::builder().decay_window(4).build();
+    stack.uninit_slice(4096, |_: &mut [MaybeUninit<u8>]| {});
+    assert_eq!(stack.capacity(), 4096);
+
+    // Light use shrinks the stack, one step per window
+    for _ in 0..4 {
+        stack.uninit_slice(10, |_: &mut [MaybeUninit<u8>]| {});
+    }
+    assert_eq!(stack.capacity(), 2048);
+    for _ in 0..100 {
+        stack.uninit_slice(10, |_: &mut [MaybeUninit<u8>]| {});
+    }
+    assert_eq!(stack.capacity(), 64);
+
+    // Use above a quarter of the capacity now and then keeps it
+    stack.uninit_slice(1024, |_: &mut [MaybeUninit<u8>]| {});
+    for i in 0..100 {
+        let len = if i % 3 == 0 { 300 } else { 10 };
+        stack.uninit_slice(len, |_: &mut [MaybeUninit<u8>]| {});
+    }
+    assert_eq!(stack.capacity(), 1024);
+    assert_eq!(stack.bytes_in_use(), 0);
+}
+
+#[test]
+fn closures_borrow_mutable_state() {
+    let stack = Stack::new();
+    let mut total = 0;
+    for len in 0..10 {
+        stack.buffer(0..len, |items| total += items.iter().sum::<u32>());
+        stack.uninit_slice(len as usize, |slice: &mut [MaybeUninit<u32>]| {
+            total += slice.len() as u32;
+        });
+    }
+    stack.buffer_chunks(0..10, 3, |chunk| total += chunk.iter().sum::<u32>());
+    assert_eq!(total, 120 + 45 + 45);
+}
+
+#[test]
+fn uninit_slice2_back_to_back() {
+    let stack = Stack::new();
+    stack.uninit_slice2::<u8, u64, _, _>(3, 5, |a, b| {
+        assert_eq!((a.len(), b.len()), (3, 5));
+        assert_eq!(b.as_ptr() as usize % std::mem::align_of::<u64>(), 0);
+        // Both fit in the first allocation, with only padding between them
+        let gap = b.as_ptr() as usize - a.as_ptr() as usize;
+        assert!(gap >= 3 && gap < 3 + std::mem::align_of::<u64>());
+    });
+    assert_eq!(stack.bytes_in_use(), 0);
+
+    // Growing for both at once keeps them in the same allocation
+    stack.uninit_slice::<u8, _, _>(1, |_| {
+        stack.uninit_slice2::<u16, u32, _, _>(10_000, 10_000, |a, b| {
+            let start = a.as_ptr() as usize;
+            let end = b.as_ptr() as usize + b.len() * 4;
+            assert!(end - start <= stack.bytes_in_use());
+        });
+    });
+    assert_eq!(stack.bytes_in_use(), 0);
+}
+
+#[test]
+fn uninit_slice2_zst_and_empty() {
+    let stack = Stack::new();
+    stack.uninit_slice2::<(), u32, _, _>(100, 0, |a, b| {
+        assert_eq!((a.len(), b.len()), (100, 0));
+    });
+    stack.uninit_slice2::<u32, (), _, _>(4, 100, |a, b| {
+        assert_eq!((a.len(), b.len()), (4, 100));
+        assert_eq!(stack.bytes_in_use(), 16);
+    });
+    stack.uninit_slice2::<u8, u8, _, _>(0, 0, |a, b| {
+        assert!(a.is_empty() && b.is_empty());
+    });
+    assert_eq!(stack.bytes_in_use(), 0);
+}
+
+#[test]
+fn buffer_sorted_sorts() {
+    let stack = Stack::new();
+    stack.buffer_sorted([5, 3, 9, 1].into_iter(), |items| {
+        assert_eq!(items, [1, 3, 5, 9]);
+    });
+    buffer_sorted_by(
+        [5, 3, 9, 1].into_iter(),
+        |a, b| b.cmp(a),
+        |items| {
+            assert_eq!(items, [9, 5, 3, 1]);
+        },
+    );
+    assert_eq!(stack.bytes_in_use(), 0);
+}
+
+#[test]
+fn buffer_sorted_by_panicking_compare_drops_items() {
+    let td = TestDrop::new();
+    let stack = Stack::new();
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let items = (0..100).map(|i| (99 - i, td.new_item().1));
+        let mut compares = 0;
+        stack.buffer_sorted_by(
+            items,
+            |a, b| {
+                compares += 1;
+                if compares == 50 {
+                    panic!("in compare");
+                }
+                a.0.cmp(&b.0)
+            },
+            |_| unreachable!(),
+        );
+    }));
+    assert!(result.is_err());
+    assert_eq!(td.num_tracked_items(), 100);
+    assert_eq!(td.num_dropped_items(), 100);
+    assert_eq!(stack.bytes_in_use(), 0);
+}
+
+#[test]
+fn uninit_array_has_len_n() {
+    let stack = Stack::new();
+    let sum = stack.uninit_array::<u32, 4, _, _>(|array| {
+        for (i, item) in array.iter_mut().enumerate() {
+            item.write(i as u32);
+        }
+        assert_eq!(stack.bytes_in_use(), 16);
+        unsafe { array[1].assume_init() + array[3].assume_init() }
+    });
+    assert_eq!(sum, 4);
+    assert_eq!(stack.bytes_in_use(), 0);
+
+    uninit_array(|array: &mut [MaybeUninit<u64>; 0]| assert!(array.is_empty()));
+    uninit_array(|array: &mut [MaybeUninit<()>; 3]| assert_eq!(array.len(), 3));
 }
@@ -0,0 +1,45 @@
+//! Tests for the pool feature, which count calls to the global allocator.
+#![cfg(feature = "pool")]
+
+use second_stack::*;
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+    thread,
+};
+
+struct Counting;
+
+thread_local!(
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) }
+);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+#[test]
+fn new_thread_reuses_allocation() {
+    let use_stack = || {
+        let before = ALLOCATIONS.with(Cell::get);
+        buffer(0..100u64, |items| assert_eq!(items.len(), 100));
+        ALLOCATIONS.with(Cell::get) - before
+    };
+
+    // The first thread's stack is freed into the pool when it exits
+    let allocations = thread::spawn(use_stack).join().unwrap();
+    assert_eq!(allocations, 1);
+
+    let allocations = thread::spawn(use_stack).join().unwrap();
+    assert_eq!(allocations, 0);
+}
@@ -0,0 +1,227 @@
+use second_stack::*;
+use std::cell::RefCell;
+use testdrop::TestDrop;
+
+struct Logged<'a> {
+    id: u32,
+    log: &'a RefCell<Vec<u32>>,
+}
+
+impl Drop for Logged<'_> {
+    fn drop(&mut self) {
+        self.log.borrow_mut().push(self.id);
+    }
+}
+
+fn fill(scope: &StackScope, depth: u32) -> u32 {
+    let items = scope.buffer(0..depth * 100);
+    let total = if depth == 0 {
+        0
+    } else {
+        fill(scope, depth - 1)
+    };
+    // Earlier slices remain intact while later ones are allocated
+    assert!(items.iter().copied().eq(0..depth * 100));
+    total + items.len() as u32
+}
+
+#[test]
+fn scope_slices_live_together() {
+    let stack = Stack::new();
+    let total = stack.scope(|scope| {
+        let a = scope.buffer(0..10u8);
+        let b = scope.buffer((0..10u64).map(|i| i * 2));
+        a[0] = 100;
+        b[0] = 200;
+        assert_eq!(a[..3], [100, 1, 2]);
+        assert_eq!(b[..3], [200, 2, 4]);
+        // Enough to grow the stack several times while the scope is open
+        fill(scope, 20)
+    });
+    assert_eq!(total, (0..=20).map(|d| d * 100).sum());
+
+    // The stack is reusable after the scope
+    stack.buffer(0..5, |items| assert_eq!(items, &[0, 1, 2, 3, 4]));
+}
+
+#[test]
+fn scope_drops_in_reverse_order() {
+    let log = RefCell::new(Vec::new());
+    let td = TestDrop::new();
+    let stack = Stack::new();
+
+    stack.scope(|scope| {
+        scope.buffer((0..2).map(|id| Logged { id, log: &log }));
+        scope.buffer((0..1000).map(|_| td.new_item().1));
+        scope.buffer((2..4).map(|id| Logged { id, log: &log }));
+        assert!(log.borrow().is_empty());
+    });
+
+    assert_eq!(*log.borrow(), [2, 3, 0, 1]);
+    assert_eq!(td.num_tracked_items(), 1000);
+    assert_eq!(td.num_dropped_items(), 1000);
+}
+
+#[test]
+fn scope_nested_in_closures() {
+    let stack = Stack::new();
+    stack.uninit_slice(10, |outer: &mut [std::mem::MaybeUninit<u32>]| {
+        stack.scope(|scope| {
+            let items = scope.buffer(0..100u32);
+            // Temporary allocations between uses of the scope are fine
+            stack.buffer(0..10000u32, |tmp| assert_eq!(tmp.len(), 10000));
+            let more = scope.buffer(0..100u32);
+            assert_eq!(items, more);
+        });
+        assert_eq!(outer.len(), 10);
+    });
+}
+
+#[test]
+fn scope_panic_drops_items() {
+    let td = TestDrop::new();
+    let stack = Stack::new();
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        stack.scope(|scope| {
+            scope.buffer((0..10).map(|_| td.new_item().1));
+            scope.buffer((0..10).map(|i| {
+                if i == 5 {
+                    panic!("iterator failed");
+                }
+                td.new_item().1
+            }));
+        });
+    }));
+    assert!(result.is_err());
+    assert_eq!(td.num_tracked_items(), 15);
+    assert_eq!(td.num_dropped_items(), 15);
+}
+
+#[test]
+#[should_panic(expected = "second-stack: scope used while a nested allocation is live")]
+fn scope_used_above_nested_allocation() {
+    let stack = Stack::new();
+    stack.scope(|scope| {
+        stack.buffer(0..10u32, |_| {
+            scope.buffer(0..10u32);
+        });
+    });
+}
+
+#[test]
+fn partial_init_guard() {
+    let td = TestDrop::new();
+    let stack = Stack::new();
+
+    stack.scope(|scope| {
+        let mut guard = scope.uninit_slice(10);
+        assert_eq!(guard.capacity(), 10);
+        for i in 0..3u32 {
+            guard.push(i);
+        }
+        let spare = guard.spare_capacity_mut();
+        spare[0].write(3);
+        spare[1].write(4);
+        unsafe { guard.assume_init(2) };
+        assert_eq!(guard.len(), 5);
+        assert_eq!(guard.into_init(), &[0, 1, 2, 3, 4]);
+
+        // Dropping the guard drops only the initialized prefix
+        let mut dropped = scope.uninit_slice(10);
+        for _ in 0..4 {
+            dropped.push(td.new_item().1);
+        }
+        drop(dropped);
+        assert_eq!(td.num_dropped_items(), 4);
+
+        // Committed items are dropped with the scope
+        let mut kept = scope.uninit_slice(10);
+        for _ in 0..6 {
+            kept.push(td.new_item().1);
+        }
+        assert_eq!(kept.into_init().len(), 6);
+        assert_eq!(td.num_dropped_items(), 4);
+    });
+
+    assert_eq!(td.num_tracked_items(), 10);
+    assert_eq!(td.num_dropped_items(), 10);
+}
+
+#[test]
+#[should_panic(expected = "second-stack: PartialInitGuard is full")]
+fn partial_init_guard_full() {
+    Stack::new().scope(|scope| {
+        let mut guard = scope.uninit_slice(1);
+        guard.push(1u8);
+        guard.push(2u8);
+    });
+}
+
+#[test]
+fn scope_without_allocations_across_growth() {
+    let stack = Stack::new();
+    stack.reserve(64);
+    stack.scope(|_scope| {
+        // Grows the stack, freeing the idle allocation the scope started with
+        stack.uninit_slice(1000, |_: &mut [std::mem::MaybeUninit<u64>]| {});
+    });
+}
+
+#[test]
+fn clear_inside_scope() {
+    let stack = Stack::new();
+    stack.reserve(64);
+    stack.scope(|scope| {
+        stack.clear();
+        let items = scope.buffer(0..10u32);
+        stack.clear();
+        assert_eq!(items.iter().sum::<u32>(), 45);
+    });
+    assert_eq!(stack.bytes_in_use(), 0);
+}
+
+#[test]
+fn alloc_slice_nested_scopes() {
+    let stack = Stack::new();
+    stack.scope(|outer| {
+        let a = outer.alloc_slice::<u32>(100);
+        for (i, item) in a.iter_mut().enumerate() {
+            item.write(i as u32);
+        }
+        stack.scope(|inner| {
+            let b = inner.alloc_slice::<u64>(1000);
+            assert_eq!(b.len(), 1000);
+            b[999].write(1);
+        });
+        // The outer scope can allocate again once the inner one is done
+        let c = outer.alloc_slice::<u8>(10);
+        assert_eq!(c.len(), 10);
+        let a = unsafe { &*(a as *const [std::mem::MaybeUninit<u32>] as *const [u32]) };
+        assert!(a.iter().copied().eq(0..100));
+    });
+    assert_eq!(stack.bytes_in_use(), 0);
+
+    // Unwinding out of nested scopes restores the stack
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        stack.scope(|outer| {
+            outer.alloc_slice::<u8>(10);
+            stack.scope(|inner| {
+                inner.alloc_slice::<u8>(10_000);
+                panic!("in scope");
+            });
+        });
+    }));
+    assert!(result.is_err());
+    assert_eq!(stack.bytes_in_use(), 0);
+}
+
+#[test]
+fn threadlocal_scope() {
+    let log = RefCell::new(Vec::new());
+    scope(|scope| {
+        let keys = scope.alloc_slice::<u32>(4);
+        let values = scope.buffer((0..4).map(|id| Logged { id, log: &log }));
+        assert_eq!(keys.len(), values.len());
+    });
+    assert_eq!(*log.borrow(), [0, 1, 2, 3]);
+}
@@ -0,0 +1,76 @@
+//! Tests for the tracing feature, using a subscriber that records the
+//! capacities reported by each event.
+#![cfg(feature = "tracing")]
+
+use second_stack::*;
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+};
+use tracing::{
+    field::{Field, Visit},
+    span, Event, Metadata, Subscriber,
+};
+
+#[derive(Clone, Default)]
+struct Recorder(Arc<Mutex<Vec<(u64, u64)>>>);
+
+#[derive(Default)]
+struct Capacities {
+    old: u64,
+    new: u64,
+}
+
+impl Visit for Capacities {
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        match field.name() {
+            "old_capacity" => self.old = value,
+            "new_capacity" => self.new = value,
+            _ => {}
+        }
+    }
+
+    fn record_debug(&mut self, _field: &Field, _value: &dyn fmt::Debug) {}
+}
+
+impl Subscriber for Recorder {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.target().starts_with("second_stack")
+    }
+
+    fn new_span(&self, _span: &span::Attributes<'_>) -> span::Id {
+        span::Id::from_u64(1)
+    }
+
+    fn record(&self, _span: &span::Id, _values: &span::Record<'_>) {}
+
+    fn record_follows_from(&self, _span: &span::Id, _follows: &span::Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut capacities = Capacities::default();
+        event.record(&mut capacities);
+        self.0
+            .lock()
+            .unwrap()
+            .push((capacities.old, capacities.new));
+    }
+
+    fn enter(&self, _span: &span::Id) {}
+
+    fn exit(&self, _span: &span::Id) {}
+}
+
+#[test]
+fn growth_and_free_are_traced() {
+    let recorder = Recorder::default();
+    tracing::subscriber::with_default(recorder.clone(), || {
+        let stack = Stack::new();
+        stack.buffer(0..100u64, |_| ());
+        drop(stack);
+    });
+
+    let events = recorder.0.lock().unwrap().clone();
+    assert_eq!(events.first().map(|e| e.0), Some(0));
+    assert!(events.iter().any(|&(old, new)| new > old && new >= 800));
+    assert_eq!(events.last().map(|e| e.1), Some(0));
+}